@@ -1,4 +1,7 @@
 INSERT INTO users(username, password_hash)
 VALUES ('demo', '$2b$12$M/ELjth7dTOG9zB/mfYPKOUl0LD4YzLqp2ugCKPaz.9sz5OKXyKHa'); -- password demo
-INSERT INTO client_tokens(client, secret)
-VALUES ('0d2e3a43-3951-4a9f-9e3b-7b7e5a2760cd', 'somesecret');
\ No newline at end of file
+INSERT INTO client_tokens(client, secret_hash, user_id)
+SELECT '0d2e3a43-3951-4a9f-9e3b-7b7e5a2760cd', '$2b$10$OwpMQltGfTjSLHNudbWil.Ktm/XqL5TQOSdlT1ASUxd9c8Q0kZNjC', users.id -- secret somesecret
+FROM users WHERE users.username = 'demo';
+INSERT INTO users_to_roles(user_id, role_id)
+SELECT users.id, roles.id FROM users, roles WHERE users.username = 'demo' AND roles.name = 'admin';
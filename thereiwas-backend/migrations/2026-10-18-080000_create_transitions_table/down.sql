@@ -0,0 +1 @@
+DROP TABLE transitions;
\ No newline at end of file
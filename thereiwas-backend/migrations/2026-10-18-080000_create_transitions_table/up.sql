@@ -0,0 +1,19 @@
+-- the table which will hold the region enter and leave events the smartphone reported
+CREATE TABLE transitions
+(
+    id                  SERIAL PRIMARY KEY,
+    event               VARCHAR(5)   NOT NULL,            -- enter, leave
+    region_id           VARCHAR(64)  DEFAULT NULL,        -- the rid of the region which was entered or left
+    region_description  VARCHAR(255) DEFAULT NULL,        -- the description of the region which was entered or left
+    region_created_at   TIMESTAMP    NOT NULL,            -- the time the region was created on the device
+    report_trigger      VARCHAR(1)   NOT NULL DEFAULT '?', -- c (circular region), b (beacon region), l (location)
+    horizontal_accuracy INT          DEFAULT NULL,
+    latitude            FLOAT        NOT NULL,
+    longitude           FLOAT        NOT NULL,
+    measurement_time    TIMESTAMP    NOT NULL,
+    reporting_device    INT          NOT NULL
+        constraint transitions_client_tokens_id_fk references client_tokens on delete cascade,
+
+    -- the same device can not enter or leave the same region twice at the same time
+    constraint transitions_unique_key unique (event, region_created_at, measurement_time, reporting_device)
+);
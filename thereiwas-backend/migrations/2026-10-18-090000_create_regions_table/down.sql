@@ -0,0 +1 @@
+DROP TABLE regions;
\ No newline at end of file
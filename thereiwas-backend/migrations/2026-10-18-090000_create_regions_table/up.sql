@@ -0,0 +1,19 @@
+-- the table which will hold the regions (waypoints) which are monitored by the smartphones
+CREATE TABLE regions
+(
+    id               SERIAL PRIMARY KEY,
+    reporting_device INT          NOT NULL
+        constraint regions_client_tokens_id_fk references client_tokens on delete cascade,
+    region_id        VARCHAR(64)  DEFAULT NULL, -- the rid of the region
+    description      VARCHAR(255) NOT NULL,
+    latitude         FLOAT        NOT NULL,
+    longitude        FLOAT        NOT NULL,
+    radius           INT          NOT NULL DEFAULT 0, -- the radius of the region in meters
+    beacon_uuid      VARCHAR(36)  DEFAULT NULL,
+    beacon_major     INT          DEFAULT NULL,
+    beacon_minor     INT          DEFAULT NULL,
+    created_at       TIMESTAMP    NOT NULL,         -- the tst of the region which OwnTracks uses as its identifier
+
+    -- OwnTracks identifies a region of a device by the time it was created
+    constraint regions_unique_key unique (reporting_device, created_at)
+);
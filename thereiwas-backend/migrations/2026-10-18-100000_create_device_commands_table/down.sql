@@ -0,0 +1 @@
+DROP TABLE device_commands;
\ No newline at end of file
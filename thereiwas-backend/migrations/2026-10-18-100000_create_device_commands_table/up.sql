@@ -0,0 +1,12 @@
+-- the table which will hold the OwnTracks messages which should be delivered to a device with its next request
+CREATE TABLE device_commands
+(
+    id               SERIAL PRIMARY KEY,
+    reporting_device INT       NOT NULL
+        constraint device_commands_client_tokens_id_fk references client_tokens on delete cascade,
+    payload          JSONB     NOT NULL,          -- the message (cmd, card) as it will be sent to the device
+    created_at       TIMESTAMP NOT NULL DEFAULT NOW(),
+    delivered_at     TIMESTAMP DEFAULT NULL       -- the time the message was handed to the device or NULL if still pending
+);
+
+CREATE INDEX device_commands_pending_idx ON device_commands (reporting_device) WHERE delivered_at IS NULL;
@@ -0,0 +1,2 @@
+DROP TABLE location_shares;
+DROP TABLE device_cards;
\ No newline at end of file
@@ -0,0 +1,22 @@
+-- the table which will hold the name and picture which should be shown to friends of a device
+CREATE TABLE device_cards
+(
+    id               SERIAL PRIMARY KEY,
+    reporting_device INT          NOT NULL UNIQUE
+        constraint device_cards_client_tokens_id_fk references client_tokens on delete cascade,
+    tracker_id       VARCHAR(2)   DEFAULT NULL, -- the two character tid which is shown on the map
+    name             VARCHAR(128) NOT NULL,
+    face             TEXT         DEFAULT NULL  -- a base64 encoded PNG image
+);
+
+-- the table which will hold which device is allowed to see the location of another device
+CREATE TABLE location_shares
+(
+    id             SERIAL PRIMARY KEY,
+    sharing_device INT NOT NULL
+        constraint location_shares_sharing_device_fk references client_tokens on delete cascade,
+    viewing_device INT NOT NULL
+        constraint location_shares_viewing_device_fk references client_tokens on delete cascade,
+
+    constraint location_shares_unique_key unique (sharing_device, viewing_device)
+);
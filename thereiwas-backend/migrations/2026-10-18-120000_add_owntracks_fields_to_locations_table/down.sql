@@ -0,0 +1,10 @@
+ALTER TABLE locations
+    DROP COLUMN battery_level,
+    DROP COLUMN battery_status,
+    DROP COLUMN monitoring_mode,
+    DROP COLUMN velocity,
+    DROP COLUMN course_over_ground,
+    DROP COLUMN connection_type,
+    DROP COLUMN topic,
+    DROP COLUMN in_regions,
+    DROP COLUMN in_region_ids;
@@ -0,0 +1,10 @@
+ALTER TABLE locations
+    ADD battery_level      SMALLINT     DEFAULT NULL, -- battery level in percent
+    ADD battery_status     SMALLINT     DEFAULT NULL, -- 0 unknown, 1 unplugged, 2 charging, 3 full
+    ADD monitoring_mode    SMALLINT     DEFAULT NULL, -- -1 quiet, 0 manual, 1 significant, 2 move
+    ADD velocity           INT          DEFAULT NULL, -- velocity in km/h
+    ADD course_over_ground INT          DEFAULT NULL, -- course over ground in degrees
+    ADD connection_type    VARCHAR(1)   DEFAULT NULL, -- w (WiFi), o (offline), m (mobile data)
+    ADD topic              VARCHAR(255) DEFAULT NULL, -- the MQTT topic the device would have published to
+    ADD in_regions         TEXT[]       DEFAULT NULL, -- the descriptions of the regions the device was in
+    ADD in_region_ids      TEXT[]       DEFAULT NULL; -- the rids of the regions the device was in
@@ -0,0 +1,32 @@
+-- the table which will hold the status reports the smartphones sent
+CREATE TABLE device_status
+(
+    id                                       SERIAL PRIMARY KEY,
+    reporting_device                         INT          NOT NULL
+        constraint device_status_client_tokens_id_fk references client_tokens on delete cascade,
+    request_id                               VARCHAR(64)  DEFAULT NULL, -- the _id of the status message (Android)
+    received_at                              TIMESTAMP    NOT NULL,
+
+    -- the information reported by the iOS app
+    altimeter_authorization_status           VARCHAR(64)  DEFAULT NULL,
+    altimeter_is_relative_altitude_available BOOLEAN      DEFAULT NULL,
+    background_refresh_status                VARCHAR(64)  DEFAULT NULL,
+    device_identifier_for_vendor             VARCHAR(36)  DEFAULT NULL,
+    device_model                             VARCHAR(64)  DEFAULT NULL,
+    device_system_name                       VARCHAR(64)  DEFAULT NULL,
+    device_system_version                    VARCHAR(32)  DEFAULT NULL,
+    device_user_interface_idiom              VARCHAR(64)  DEFAULT NULL,
+    locale                                   VARCHAR(32)  DEFAULT NULL,
+    locale_uses_metric_system                BOOLEAN      DEFAULT NULL,
+    location_manager_authorization_status    VARCHAR(64)  DEFAULT NULL,
+    app_version                              VARCHAR(32)  DEFAULT NULL,
+
+    -- the information reported by the Android app
+    app_hibernation                          INT          DEFAULT NULL, -- hib
+    battery_optimizations                    INT          DEFAULT NULL, -- bo
+    location_permission                      INT          DEFAULT NULL, -- loc
+    power_save                               INT          DEFAULT NULL, -- ps
+    wifi_state                               INT          DEFAULT NULL  -- wifi
+);
+
+CREATE INDEX device_status_reporting_device_idx ON device_status (reporting_device, received_at);
@@ -0,0 +1 @@
+ALTER TABLE client_tokens DROP COLUMN encryption_key;
\ No newline at end of file
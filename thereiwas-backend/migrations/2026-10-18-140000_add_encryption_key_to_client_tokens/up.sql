@@ -0,0 +1,2 @@
+-- the secret which is used by OwnTracks to encrypt the payload of the messages
+ALTER TABLE client_tokens ADD encryption_key VARCHAR(255) DEFAULT NULL;
\ No newline at end of file
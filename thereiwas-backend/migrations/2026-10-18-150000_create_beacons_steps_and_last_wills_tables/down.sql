@@ -0,0 +1,3 @@
+DROP TABLE last_wills;
+DROP TABLE step_counts;
+DROP TABLE beacon_sightings;
@@ -0,0 +1,48 @@
+-- the table which will hold the iBeacons the smartphone ranged
+CREATE TABLE beacon_sightings
+(
+    id                  SERIAL PRIMARY KEY,
+    description         VARCHAR(255) DEFAULT NULL, -- the description of the region the beacon belongs to
+    beacon_uuid         VARCHAR(36)  NOT NULL,
+    beacon_major        INT          NOT NULL,
+    beacon_minor        INT          NOT NULL,
+    proximity           INT          NOT NULL,     -- 0 (unknown), 1 (immediate), 2 (near), 3 (far)
+    rssi                INT          NOT NULL,     -- the received signal strength in dBm
+    horizontal_accuracy INT          DEFAULT NULL, -- the accuracy of the proximity value in meters
+    measurement_time    TIMESTAMP    NOT NULL,
+    reporting_device    INT          NOT NULL
+        constraint beacon_sightings_client_tokens_id_fk references client_tokens on delete cascade,
+
+    -- the same device can not see the same beacon twice at the same time
+    constraint beacon_sightings_unique_key unique (beacon_uuid, beacon_major, beacon_minor, measurement_time, reporting_device)
+);
+
+-- the table which will hold the pedometer reports of the smartphone
+CREATE TABLE step_counts
+(
+    id               SERIAL PRIMARY KEY,
+    steps            INT       NOT NULL,
+    distance         INT       DEFAULT NULL, -- the walked distance in meters
+    floors_up        INT       DEFAULT NULL,
+    floors_down      INT       DEFAULT NULL,
+    period_start     TIMESTAMP NOT NULL,
+    period_end       TIMESTAMP NOT NULL,
+    measurement_time TIMESTAMP NOT NULL,
+    reporting_device INT       NOT NULL
+        constraint step_counts_client_tokens_id_fk references client_tokens on delete cascade,
+
+    -- the same device can not report the steps for the same period twice
+    constraint step_counts_unique_key unique (period_start, period_end, reporting_device)
+);
+
+-- the table which will hold the last will messages (sent if the connection was lost) of the smartphone
+CREATE TABLE last_wills
+(
+    id               SERIAL PRIMARY KEY,
+    connected_at     TIMESTAMP NOT NULL, -- the time the app connected for the first time
+    received_at      TIMESTAMP NOT NULL,
+    reporting_device INT       NOT NULL
+        constraint last_wills_client_tokens_id_fk references client_tokens on delete cascade,
+
+    constraint last_wills_unique_key unique (connected_at, reporting_device)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE client_tokens DROP COLUMN reported_device;
+ALTER TABLE client_tokens DROP COLUMN reported_user;
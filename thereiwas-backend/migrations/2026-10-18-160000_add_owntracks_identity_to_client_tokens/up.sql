@@ -0,0 +1,3 @@
+-- the user and device names the OwnTracks app reported in the X-Limit-U and X-Limit-D headers
+ALTER TABLE client_tokens ADD reported_user VARCHAR(255) DEFAULT NULL;
+ALTER TABLE client_tokens ADD reported_device VARCHAR(255) DEFAULT NULL;
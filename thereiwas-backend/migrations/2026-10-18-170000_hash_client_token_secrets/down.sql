@@ -0,0 +1,12 @@
+-- the hashed secrets can not be turned back into plaintext secrets, so this migration can only be
+-- reverted after the clients were deleted. they have to be provisioned again afterwards
+DO
+$$
+    BEGIN
+        IF EXISTS (SELECT 1 FROM client_tokens) THEN
+            RAISE EXCEPTION 'the client secrets are hashed and can not be restored, delete the client tokens before reverting this migration';
+        END IF;
+    END
+$$;
+ALTER TABLE client_tokens ALTER COLUMN secret_hash TYPE VARCHAR(10);
+ALTER TABLE client_tokens RENAME COLUMN secret_hash TO secret;
@@ -0,0 +1,7 @@
+-- the secrets of the clients are stored as bcrypt hashes from now on. the existing plaintext
+-- secrets are not hashed by this migration but by the backend on its next start (see
+-- `hash_plaintext_client_secrets`). every secret which does not start with `$2` (the prefix of
+-- all bcrypt hashes) is treated as plaintext secret there, so a plaintext secret starting with
+-- `$2` is kept as it is and the client has to be provisioned with a new secret
+ALTER TABLE client_tokens RENAME COLUMN secret TO secret_hash;
+ALTER TABLE client_tokens ALTER COLUMN secret_hash TYPE VARCHAR(60); -- a bcrypt hash should be max. 60 characters
@@ -0,0 +1,6 @@
+DELETE
+FROM roles_to_permissions
+WHERE role_id = (SELECT id FROM roles WHERE name = 'admin');
+
+ALTER TABLE roles_to_permissions DROP CONSTRAINT roles_to_permissions_unique_key;
+ALTER TABLE users_to_roles DROP CONSTRAINT users_to_roles_unique_key;
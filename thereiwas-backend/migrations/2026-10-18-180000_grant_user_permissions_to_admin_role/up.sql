@@ -0,0 +1,11 @@
+-- a user should not have the same role twice and a role should not have the same permission twice
+ALTER TABLE users_to_roles ADD CONSTRAINT users_to_roles_unique_key UNIQUE (user_id, role_id);
+ALTER TABLE roles_to_permissions ADD CONSTRAINT roles_to_permissions_unique_key UNIQUE (role_id, permission_id);
+
+-- the admin role is allowed to do everything which is covered by the existing permissions
+INSERT INTO roles_to_permissions (role_id, permission_id)
+SELECT roles.id, permissions.id
+FROM roles,
+     permissions
+WHERE roles.name = 'admin'
+ON CONFLICT DO NOTHING;
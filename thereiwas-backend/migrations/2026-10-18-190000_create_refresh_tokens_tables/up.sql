@@ -0,0 +1,19 @@
+-- the table which will hold the long-lived tokens which can be used to get new access tokens
+CREATE TABLE refresh_tokens
+(
+    id         SERIAL PRIMARY KEY,
+    user_id    INT         NOT NULL
+        constraint refresh_tokens_users_id_fk references users on delete cascade,
+    token_hash VARCHAR(64) NOT NULL UNIQUE, -- the hex encoded SHA-256 hash of the token
+    created_at TIMESTAMP   NOT NULL,
+    expires_at TIMESTAMP   NOT NULL,
+    revoked_at TIMESTAMP DEFAULT NULL       -- set if the token was used (rotated) or the user logged out
+);
+
+-- the table which will hold the access tokens which were revoked before they expired
+CREATE TABLE revoked_access_tokens
+(
+    id         SERIAL PRIMARY KEY,
+    token_id   VARCHAR(32) NOT NULL UNIQUE, -- the jti claim of the token
+    expires_at TIMESTAMP   NOT NULL         -- the entry can be removed after the token expired
+);
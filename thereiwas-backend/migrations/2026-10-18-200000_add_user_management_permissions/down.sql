@@ -0,0 +1,9 @@
+DELETE
+FROM roles_to_permissions
+WHERE permission_id IN (SELECT id FROM permissions WHERE name IN ('create:users', 'edit:users'));
+
+DELETE
+FROM permissions
+WHERE name IN ('create:users', 'edit:users');
+
+ALTER TABLE users DROP CONSTRAINT users_username_unique_key;
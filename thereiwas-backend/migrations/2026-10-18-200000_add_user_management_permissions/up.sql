@@ -0,0 +1,15 @@
+-- users are identified by their username when they log in, so it has to be unique
+ALTER TABLE users ADD CONSTRAINT users_username_unique_key UNIQUE (username);
+
+INSERT INTO permissions
+VALUES (DEFAULT, 'create:users', 'Create new users on this instance'),
+       (DEFAULT, 'edit:users', 'Change the roles of the users of this instance');
+
+-- the admin role is allowed to manage the users of the instance
+INSERT INTO roles_to_permissions (role_id, permission_id)
+SELECT roles.id, permissions.id
+FROM roles,
+     permissions
+WHERE roles.name = 'admin'
+  AND permissions.name IN ('create:users', 'edit:users')
+ON CONFLICT DO NOTHING;
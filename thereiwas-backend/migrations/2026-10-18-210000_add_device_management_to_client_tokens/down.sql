@@ -0,0 +1,11 @@
+DELETE
+FROM roles_to_permissions
+WHERE permission_id = (SELECT id FROM permissions WHERE name = 'manage:devices');
+
+DELETE
+FROM permissions
+WHERE name = 'manage:devices';
+
+ALTER TABLE client_tokens DROP COLUMN revoked_at;
+ALTER TABLE client_tokens DROP COLUMN created_at;
+ALTER TABLE client_tokens DROP CONSTRAINT client_tokens_client_unique_key;
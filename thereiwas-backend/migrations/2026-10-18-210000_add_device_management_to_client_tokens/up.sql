@@ -0,0 +1,15 @@
+-- the client id is used for looking up the device while authenticating, so it has to be unique
+ALTER TABLE client_tokens ADD CONSTRAINT client_tokens_client_unique_key UNIQUE (client);
+ALTER TABLE client_tokens ADD created_at TIMESTAMP NOT NULL DEFAULT now();
+ALTER TABLE client_tokens ADD revoked_at TIMESTAMP DEFAULT NULL; -- a revoked device can not report anything anymore
+
+INSERT INTO permissions
+VALUES (DEFAULT, 'manage:devices', 'Register, change and revoke the devices of this instance');
+
+INSERT INTO roles_to_permissions (role_id, permission_id)
+SELECT roles.id, permissions.id
+FROM roles,
+     permissions
+WHERE roles.name = 'admin'
+  AND permissions.name = 'manage:devices'
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,15 @@
+DELETE
+FROM roles_to_permissions
+WHERE role_id = (SELECT id FROM roles WHERE name = 'user')
+  AND permission_id = (SELECT id FROM permissions WHERE name = 'manage:devices');
+UPDATE permissions SET description = 'Register, change and revoke the devices of this instance' WHERE name = 'manage:devices';
+
+DELETE
+FROM roles_to_permissions
+WHERE permission_id = (SELECT id FROM permissions WHERE name = 'access:all:devices');
+
+DELETE
+FROM permissions
+WHERE name = 'access:all:devices';
+
+ALTER TABLE client_tokens DROP COLUMN user_id;
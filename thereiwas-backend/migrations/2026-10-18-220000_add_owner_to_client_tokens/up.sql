@@ -0,0 +1,28 @@
+-- the user who owns the device, devices without an owner can only be accessed with the 'access:all:devices' permission
+ALTER TABLE client_tokens
+    ADD user_id INT DEFAULT NULL
+        constraint client_tokens_users_id_fk references users on delete set null;
+
+-- until now, all devices were used by the first user of the instance
+UPDATE client_tokens SET user_id = (SELECT min(id) FROM users) WHERE user_id IS NULL;
+
+INSERT INTO permissions
+VALUES (DEFAULT, 'access:all:devices', 'Access the devices and locations of all users of this instance');
+
+INSERT INTO roles_to_permissions (role_id, permission_id)
+SELECT roles.id, permissions.id
+FROM roles,
+     permissions
+WHERE roles.name = 'admin'
+  AND permissions.name = 'access:all:devices'
+ON CONFLICT DO NOTHING;
+
+-- every user should be able to manage their own devices
+UPDATE permissions SET description = 'Register, change and revoke your own devices' WHERE name = 'manage:devices';
+INSERT INTO roles_to_permissions (role_id, permission_id)
+SELECT roles.id, permissions.id
+FROM roles,
+     permissions
+WHERE roles.name = 'user'
+  AND permissions.name = 'manage:devices'
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,2 @@
+DROP INDEX locations_reporting_device_idx;
+DROP INDEX locations_measurement_time_idx;
@@ -0,0 +1,3 @@
+-- the positions are paged by their measurement time and id, optionally filtered by the reporting device
+CREATE INDEX locations_measurement_time_idx ON locations (measurement_time DESC, id DESC);
+CREATE INDEX locations_reporting_device_idx ON locations (reporting_device, measurement_time DESC, id DESC);
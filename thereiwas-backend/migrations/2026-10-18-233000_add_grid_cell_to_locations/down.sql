@@ -0,0 +1,4 @@
+DROP INDEX locations_grid_cell_idx;
+ALTER TABLE locations DROP COLUMN grid_cell;
+DROP FUNCTION haversine_distance(DOUBLE PRECISION, DOUBLE PRECISION, DOUBLE PRECISION, DOUBLE PRECISION);
+DROP FUNCTION location_grid_cell(DOUBLE PRECISION, DOUBLE PRECISION);
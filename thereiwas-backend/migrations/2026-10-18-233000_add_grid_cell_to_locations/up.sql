@@ -0,0 +1,23 @@
+-- the number of the cell of a grid with cells of 0.1 x 0.1 degrees the location is in; the cells are numbered row by row
+-- starting at the south-west corner; the northern and eastern borders belong to the last row or column (has to match
+-- the calculation in geo.rs)
+CREATE FUNCTION location_grid_cell(latitude DOUBLE PRECISION, longitude DOUBLE PRECISION) RETURNS INT
+    LANGUAGE sql
+    IMMUTABLE
+    PARALLEL SAFE
+RETURN least(floor((latitude + 90) * 10)::INT, 1799) * 3600 + least(floor((longitude + 180) * 10)::INT, 3599);
+
+-- the great-circle distance in meters between two points; the argument of asin is limited to 1 since
+-- rounding errors can push it slightly above for (nearly) antipodal points
+CREATE FUNCTION haversine_distance(latitude_a DOUBLE PRECISION, longitude_a DOUBLE PRECISION,
+                                   latitude_b DOUBLE PRECISION, longitude_b DOUBLE PRECISION) RETURNS DOUBLE PRECISION
+    LANGUAGE sql
+    IMMUTABLE
+    PARALLEL SAFE
+RETURN 2 * 6371008.8 * asin(least(1.0, sqrt(power(sin(radians(latitude_b - latitude_a) / 2), 2) +
+                                            cos(radians(latitude_a)) * cos(radians(latitude_b)) *
+                                            power(sin(radians(longitude_b - longitude_a) / 2), 2))));
+
+ALTER TABLE locations
+    ADD grid_cell INT NOT NULL GENERATED ALWAYS AS (location_grid_cell(latitude, longitude)) STORED;
+CREATE INDEX locations_grid_cell_idx ON locations (grid_cell, measurement_time);
@@ -0,0 +1 @@
+ALTER TABLE locations DROP CONSTRAINT locations_unique_key;
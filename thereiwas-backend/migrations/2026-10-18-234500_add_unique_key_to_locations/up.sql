@@ -0,0 +1,28 @@
+-- the unique key got lost when the topic was replaced by the reporting device, so the same location could be stored
+-- multiple times since then; only the first of these copies is kept
+CREATE TEMPORARY TABLE location_copies ON COMMIT DROP AS
+SELECT id, kept_id
+FROM (SELECT id,
+             min(id) OVER (PARTITION BY latitude, longitude, measurement_time, reporting_device) AS kept_id
+      FROM locations) AS copies
+WHERE id <> kept_id;
+
+-- the WiFi access points which were seen with a removed copy are moved to the kept location instead of being deleted
+-- together with the copy
+UPDATE locations_to_wifi_access_points
+SET location_id = location_copies.kept_id
+FROM location_copies
+WHERE locations_to_wifi_access_points.location_id = location_copies.id;
+
+DELETE FROM locations_to_wifi_access_points
+WHERE id IN (SELECT id
+             FROM (SELECT id,
+                          row_number() OVER (PARTITION BY location_id, wifi_access_point_id ORDER BY id) AS copy
+                   FROM locations_to_wifi_access_points) AS copies
+             WHERE copy > 1);
+
+DELETE FROM locations
+WHERE id IN (SELECT id FROM location_copies);
+
+ALTER TABLE locations
+    ADD constraint locations_unique_key unique (latitude, longitude, measurement_time, reporting_device);
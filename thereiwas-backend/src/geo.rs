@@ -0,0 +1,238 @@
+use diesel::sql_types::Double;
+
+/// The number of grid cells per degree of latitude or longitude (see the `location_grid_cell` SQL
+/// function).
+const GRID_CELLS_PER_DEGREE: f64 = 10.0;
+
+/// The number of grid cells in one row of the grid, i.e. for 360 degrees of longitude.
+const GRID_COLUMNS: i32 = 3600;
+
+/// The number of rows of the grid, i.e. for 180 degrees of latitude.
+const GRID_ROWS: i32 = 1800;
+
+/// The maximal number of grid cells which are used for looking up the locations in an area. For
+/// larger areas, the grid cells would not narrow down the locations anyway.
+const MAXIMUM_GRID_CELLS: usize = 2500;
+
+/// The mean radius of the earth in meters.
+pub const EARTH_RADIUS_IN_METERS: f64 = 6_371_008.8;
+
+diesel::define_sql_function! {
+    /// The great-circle distance in meters between two points (see the `haversine_distance` SQL
+    /// function).
+    fn haversine_distance(
+        latitude_a: Double,
+        longitude_a: Double,
+        latitude_b: Double,
+        longitude_b: Double,
+    ) -> Double;
+}
+
+// the northern and eastern borders belong to the last row or column, otherwise they would wrap
+// around into the next row
+fn get_grid_row(latitude: f64) -> i32 {
+    (((latitude + 90.0) * GRID_CELLS_PER_DEGREE).floor() as i32).min(GRID_ROWS - 1)
+}
+
+fn get_grid_column(longitude: f64) -> i32 {
+    (((longitude + 180.0) * GRID_CELLS_PER_DEGREE).floor() as i32).min(GRID_COLUMNS - 1)
+}
+
+/// Get the number of the grid cell the supplied coordinates are in. This has to match the
+/// `location_grid_cell` SQL function which is used for the `grid_cell` column of the locations.
+pub fn get_grid_cell(latitude: f64, longitude: f64) -> i32 {
+    get_grid_row(latitude) * GRID_COLUMNS + get_grid_column(longitude)
+}
+
+/// Get the distance in meters of the point `p` to the line segment from `a` to `b`. The points are
+/// pairs of latitude and longitude and are projected onto a plane around the segment, which is
+/// precise enough for the short distances between the points of a track.
+fn get_distance_to_segment(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let longitude_scale = ((a.0 + b.0) / 2.0).to_radians().cos();
+    let project = |(latitude, longitude): (f64, f64)| {
+        (
+            longitude.to_radians() * longitude_scale * EARTH_RADIUS_IN_METERS,
+            latitude.to_radians() * EARTH_RADIUS_IN_METERS,
+        )
+    };
+    let (px, py) = project(p);
+    let (ax, ay) = project(a);
+    let (bx, by) = project(b);
+
+    let (dx, dy) = (bx - ax, by - ay);
+    let segment_length_squared = dx * dx + dy * dy;
+    let t = if segment_length_squared == 0.0 {
+        0.0
+    } else {
+        (((px - ax) * dx + (py - ay) * dy) / segment_length_squared).clamp(0.0, 1.0)
+    };
+    ((px - ax - t * dx).powi(2) + (py - ay - t * dy).powi(2)).sqrt()
+}
+
+/// Simplify the supplied track (pairs of latitude and longitude) with the Douglas-Peucker algorithm
+/// and return which of the points should be kept. No point of the track is farther than the
+/// tolerance away from the simplified track.
+pub fn simplify_track(track: &[(f64, f64)], tolerance_in_meters: f64) -> Vec<bool> {
+    let mut keep_point = vec![false; track.len()];
+    if track.len() < 3 {
+        keep_point.fill(true);
+        return keep_point;
+    }
+    keep_point[0] = true;
+    keep_point[track.len() - 1] = true;
+
+    // the segments are processed with an explicit stack since long tracks would overflow the call stack
+    let mut segments = vec![(0, track.len() - 1)];
+    while let Some((first, last)) = segments.pop() {
+        let farthest_point = (first + 1..last)
+            .map(|index| {
+                (
+                    index,
+                    get_distance_to_segment(track[index], track[first], track[last]),
+                )
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+        if let Some((index, distance)) = farthest_point {
+            if distance > tolerance_in_meters {
+                keep_point[index] = true;
+                segments.push((first, index));
+                segments.push((index, last));
+            }
+        }
+    }
+
+    keep_point
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BoundingBox {
+    pub min_latitude: f64,
+    pub min_longitude: f64,
+    pub max_latitude: f64,
+    pub max_longitude: f64,
+}
+
+impl BoundingBox {
+    /// Get the smallest bounding box which contains the circle with the supplied center and radius.
+    pub fn around(latitude: f64, longitude: f64, radius_in_meters: f64) -> BoundingBox {
+        let latitude_delta = (radius_in_meters / EARTH_RADIUS_IN_METERS).to_degrees();
+        let min_latitude = (latitude - latitude_delta).max(-90.0);
+        let max_latitude = (latitude + latitude_delta).min(90.0);
+
+        // the degrees of longitude get shorter towards the poles, the circle might even contain a
+        // pole or cross the antimeridian in which case all longitudes have to be included
+        let farthest_latitude = min_latitude.abs().max(max_latitude.abs());
+        let longitude_delta = (radius_in_meters
+            / (EARTH_RADIUS_IN_METERS * farthest_latitude.to_radians().cos()))
+        .to_degrees();
+        let covers_all_longitudes = !longitude_delta.is_finite()
+            || longitude - longitude_delta < -180.0
+            || longitude + longitude_delta > 180.0;
+        let (min_longitude, max_longitude) = if covers_all_longitudes {
+            (-180.0, 180.0)
+        } else {
+            (longitude - longitude_delta, longitude + longitude_delta)
+        };
+
+        BoundingBox {
+            min_latitude,
+            min_longitude,
+            max_latitude,
+            max_longitude,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        (-90.0..=90.0).contains(&self.min_latitude)
+            && (-90.0..=90.0).contains(&self.max_latitude)
+            && (-180.0..=180.0).contains(&self.min_longitude)
+            && (-180.0..=180.0).contains(&self.max_longitude)
+            && self.min_latitude <= self.max_latitude
+            && self.min_longitude <= self.max_longitude
+    }
+
+    /// Get all grid cells which overlap with the bounding box or `None` if the bounding box covers
+    /// too many cells for a lookup.
+    pub fn get_grid_cells(&self) -> Option<Vec<i32>> {
+        let rows = get_grid_row(self.min_latitude)..=get_grid_row(self.max_latitude);
+        let columns = get_grid_column(self.min_longitude)..=get_grid_column(self.max_longitude);
+        if rows.clone().count() * columns.clone().count() > MAXIMUM_GRID_CELLS {
+            return None;
+        }
+
+        Some(
+            rows.flat_map(|row| {
+                columns
+                    .clone()
+                    .map(move |column| row * GRID_COLUMNS + column)
+            })
+            .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grid_cells_match_the_database_function() {
+        // values calculated by the location_grid_cell function of the database
+        assert_eq!(get_grid_cell(51.2353, 6.7829), 5085067);
+        assert_eq!(get_grid_cell(-33.9, 151.2), 2022912);
+        assert_eq!(get_grid_cell(90.0, 180.0), 6479999);
+        assert_eq!(get_grid_cell(-90.0, -180.0), 0);
+    }
+
+    #[test]
+    fn test_simplified_track_keeps_the_corners_and_drops_points_on_straight_lines() {
+        // an L-shaped track with a small zig-zag of about 1m on the first leg
+        let track = [
+            (51.0, 7.0),
+            (51.001, 7.00001),
+            (51.002, 7.0),
+            (51.003, 7.0),
+            (51.003, 7.001),
+            (51.003, 7.002),
+        ];
+
+        assert_eq!(
+            simplify_track(&track, 5.0),
+            vec![true, false, false, true, false, true]
+        );
+        assert!(simplify_track(&track, 0.1)[1]);
+        assert_eq!(simplify_track(&track[..2], 5.0), vec![true, true]);
+    }
+
+    #[test]
+    fn test_bounding_box_around_a_point_contains_the_grid_cells_of_the_circle() {
+        let bounding_box = BoundingBox::around(51.2353, 6.7829, 5000.0);
+        assert!(bounding_box.is_valid());
+        assert!((bounding_box.max_latitude - 51.2353 - 0.044966).abs() < 0.0001);
+
+        let grid_cells = bounding_box.get_grid_cells().unwrap();
+        assert!(grid_cells.contains(&get_grid_cell(51.2353, 6.7829)));
+        assert!(grid_cells.contains(&get_grid_cell(51.27, 6.83)));
+        assert!(!grid_cells.contains(&get_grid_cell(51.5, 6.7829)));
+    }
+
+    #[test]
+    fn test_bounding_box_around_a_pole_or_the_antimeridian_covers_all_longitudes() {
+        let around_pole = BoundingBox::around(89.99, 0.0, 5000.0);
+        assert_eq!(
+            (around_pole.min_longitude, around_pole.max_longitude),
+            (-180.0, 180.0)
+        );
+        assert_eq!(around_pole.max_latitude, 90.0);
+
+        let around_antimeridian = BoundingBox::around(0.0, 179.99, 5000.0);
+        assert_eq!(
+            (
+                around_antimeridian.min_longitude,
+                around_antimeridian.max_longitude
+            ),
+            (-180.0, 180.0)
+        );
+        assert!(around_antimeridian.get_grid_cells().is_none());
+    }
+}
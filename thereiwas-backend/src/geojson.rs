@@ -0,0 +1,207 @@
+use crate::models::Location;
+use chrono::NaiveDate;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize)]
+#[serde(tag = "type")]
+pub enum Geometry {
+    Point { coordinates: Vec<f64> },
+    LineString { coordinates: Vec<Vec<f64>> },
+}
+
+/// The properties of a location which are not part of the geometry.
+#[derive(Serialize)]
+pub struct LocationProperties {
+    pub id: i32,
+    pub horizontal_accuracy: Option<i32>,
+    pub altitude: Option<i32>,
+    pub report_trigger: String,
+    pub measurement_time: i64,
+    pub vertical_accuracy: Option<i32>,
+    pub barometric_pressure: Option<f64>,
+    pub created_at: Option<i64>,
+    pub reporting_device: i32,
+    pub battery_level: Option<i16>,
+    pub battery_status: Option<i16>,
+    pub monitoring_mode: Option<i16>,
+    pub velocity: Option<i32>,
+    pub course_over_ground: Option<i32>,
+    pub connection_type: Option<String>,
+    pub topic: Option<String>,
+    pub in_regions: Vec<String>,
+    pub in_region_ids: Vec<String>,
+}
+
+impl From<Location> for LocationProperties {
+    fn from(location: Location) -> Self {
+        LocationProperties {
+            id: location.id,
+            horizontal_accuracy: location.horizontal_accuracy,
+            altitude: location.altitude,
+            report_trigger: location.report_trigger,
+            measurement_time: location.measurement_time.and_utc().timestamp(),
+            vertical_accuracy: location.vertical_accuracy,
+            barometric_pressure: location.barometric_pressure,
+            created_at: location
+                .created_at
+                .map(|created_at| created_at.and_utc().timestamp()),
+            reporting_device: location.reporting_device,
+            battery_level: location.battery_level,
+            battery_status: location.battery_status,
+            monitoring_mode: location.monitoring_mode,
+            velocity: location.velocity,
+            course_over_ground: location.course_over_ground,
+            connection_type: location.connection_type,
+            topic: location.topic,
+            in_regions: location
+                .in_regions
+                .unwrap_or_default()
+                .into_iter()
+                .flatten()
+                .collect(),
+            in_region_ids: location
+                .in_region_ids
+                .unwrap_or_default()
+                .into_iter()
+                .flatten()
+                .collect(),
+        }
+    }
+}
+
+/// The properties of the track a device took on one day.
+#[derive(Serialize)]
+pub struct TrackProperties {
+    pub reporting_device: i32,
+    pub day: String,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub positions: usize,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Properties {
+    Location(Box<LocationProperties>),
+    Track(TrackProperties),
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type")]
+pub struct Feature {
+    pub geometry: Geometry,
+    pub properties: Properties,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type")]
+pub struct FeatureCollection {
+    pub features: Vec<Feature>,
+    /// The cursor which can be used to get the next (older) page of positions, if there are any.
+    pub next_cursor: Option<String>,
+    /// The number of positions which are part of the features.
+    pub returned_positions: usize,
+    /// The number of positions which were found before they were simplified.
+    pub raw_positions: usize,
+}
+
+/// Get the coordinates of a location in the order GeoJSON expects them (longitude, latitude and
+/// the altitude, if known).
+fn get_coordinates(location: &Location) -> Vec<f64> {
+    let mut coordinates = vec![location.longitude, location.latitude];
+    if let Some(altitude) = location.altitude {
+        coordinates.push(altitude as f64);
+    }
+    coordinates
+}
+
+/// Get a point feature for each of the supplied locations.
+pub fn get_point_features(locations: Vec<Location>) -> Vec<Feature> {
+    locations
+        .into_iter()
+        .map(|location| Feature {
+            geometry: Geometry::Point {
+                coordinates: get_coordinates(&location),
+            },
+            properties: Properties::Location(Box::new(LocationProperties::from(location))),
+        })
+        .collect()
+}
+
+/// Get a line feature for each day and device of the supplied locations. The lines follow the
+/// locations in the order they were measured. Days with only one location get a point instead,
+/// since a line needs at least two positions.
+pub fn get_line_features(locations: Vec<Location>) -> Vec<Feature> {
+    let mut tracks = BTreeMap::<(i32, NaiveDate), Vec<Location>>::new();
+    for location in locations {
+        tracks
+            .entry((location.reporting_device, location.measurement_time.date()))
+            .or_default()
+            .push(location);
+    }
+
+    tracks
+        .into_iter()
+        .map(|((reporting_device, day), mut track)| {
+            track.sort_by_key(|location| (location.measurement_time, location.id));
+            let geometry = if track.len() == 1 {
+                Geometry::Point {
+                    coordinates: get_coordinates(&track[0]),
+                }
+            } else {
+                Geometry::LineString {
+                    coordinates: track.iter().map(get_coordinates).collect(),
+                }
+            };
+            Feature {
+                geometry,
+                properties: Properties::Track(TrackProperties {
+                    reporting_device,
+                    day: day.format("%Y-%m-%d").to_string(),
+                    start_time: track[0].measurement_time.and_utc().timestamp(),
+                    end_time: track[track.len() - 1]
+                        .measurement_time
+                        .and_utc()
+                        .timestamp(),
+                    positions: track.len(),
+                }),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::get_test_location;
+
+    #[test]
+    fn test_line_features_are_created_per_device_and_day() {
+        // the locations are ordered like the positions endpoint returns them (newest first)
+        let line_features = get_line_features(vec![
+            get_test_location(4, 1, 1_700_090_000),
+            get_test_location(3, 2, 1_700_000_100),
+            get_test_location(2, 1, 1_700_000_100),
+            get_test_location(1, 1, 1_700_000_000),
+        ]);
+
+        assert_eq!(line_features.len(), 3);
+        match (&line_features[0].geometry, &line_features[0].properties) {
+            (Geometry::LineString { coordinates }, Properties::Track(track)) => {
+                assert_eq!(coordinates[0], vec![6.7829, 51.2353, 38.0]);
+                assert_eq!(track.day, "2023-11-14");
+                assert_eq!(
+                    (track.start_time, track.end_time, track.positions),
+                    (1_700_000_000, 1_700_000_100, 2)
+                );
+            }
+            _ => panic!("the feature should be a line of a track"),
+        }
+
+        let json = serde_json::to_value(&line_features[0]).unwrap();
+        assert_eq!(json["type"], "Feature");
+        assert_eq!(json["geometry"]["type"], "LineString");
+        assert!(matches!(line_features[1].geometry, Geometry::Point { .. }));
+    }
+}
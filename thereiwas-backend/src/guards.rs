@@ -1,30 +1,195 @@
 use crate::fairings::ThereIWasDatabaseConnection;
-use crate::models::ClientToken;
+use crate::models::{ClientToken, ReportedIdentityChange, User};
 use crate::schema::client_tokens::dsl::client_tokens;
-use crate::schema::client_tokens::{client as client_id_column, secret as client_secret_column};
-use crate::{log_audit_message, AuditLogAction, AuditLogResult};
-use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl, RunQueryDsl};
+use crate::schema::client_tokens::{client as client_id_column, revoked_at as revoked_at_column};
+use crate::schema::revoked_access_tokens::dsl::revoked_access_tokens;
+use crate::schema::revoked_access_tokens::token_id as token_id_column;
+use crate::schema::users::dsl::users;
+use crate::schema::users::username as username_column;
+use crate::{
+    generate_client_secret, log_audit_message, AuditLogAction, AuditLogResult,
+    BackendConfiguration, Claims, CLIENT_SECRET_HASH_COST,
+};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use bcrypt::{hash, verify};
+use chrono::{DateTime, NaiveDateTime};
+use diesel::{
+    ExpressionMethods, JoinOnDsl, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl,
+};
+use lazy_static::lazy_static;
 use log::{debug, error, warn};
 use rocket::http::Status;
 use rocket::request::{FromRequest, Outcome};
 use rocket::{Request, State};
 use std::net::{IpAddr, SocketAddr};
 
+lazy_static! {
+    /// A hash which is used for verifying the secret of clients which are not known.
+    static ref DUMMY_CLIENT_SECRET_HASH: String =
+        hash(generate_client_secret(), CLIENT_SECRET_HASH_COST).unwrap();
+}
+
+pub struct AuthenticatedUser {
+    pub id: i32,
+    pub username: String,
+    /// The names of all permissions the user got through the assigned roles.
+    pub permissions: Vec<String>,
+    /// The unique identifier (`jti`) of the access token which was used for the request.
+    pub token_id: String,
+    /// The time the access token which was used for the request expires.
+    pub token_expires_at: NaiveDateTime,
+}
+
+/// The permission which allows a user to access the devices and locations of all users.
+pub const ACCESS_ALL_DEVICES_PERMISSION: &str = "access:all:devices";
+
+impl AuthenticatedUser {
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|granted| granted == permission)
+    }
+
+    /// Get the ids of the devices the user is allowed to access or `None` if the user is allowed
+    /// to access all devices of the instance.
+    pub fn get_accessible_devices(
+        &self,
+        db_connection: &mut PgConnection,
+    ) -> Result<Option<Vec<i32>>, diesel::result::Error> {
+        use crate::schema::client_tokens::{id as device_id_column, user_id as owner_column};
+
+        if self.has_permission(ACCESS_ALL_DEVICES_PERMISSION) {
+            return Ok(None);
+        }
+
+        client_tokens
+            .filter(owner_column.eq(self.id))
+            .select(device_id_column)
+            .load::<i32>(db_connection)
+            .map(Some)
+    }
+
+    /// Ensure that the user is allowed to access everything which was reported by the supplied
+    /// device.
+    pub fn ensure_device_access(
+        &self,
+        device_id: i32,
+        db_connection: &mut PgConnection,
+    ) -> Result<(), Status> {
+        let accessible_devices = self
+            .get_accessible_devices(db_connection)
+            .map_err(|error| {
+                error!(
+                    "Could not query the devices of the user '{}'. The error was: {}",
+                    self.username, error
+                );
+                Status::InternalServerError
+            })?;
+        if accessible_devices.is_some_and(|devices| !devices.contains(&device_id)) {
+            warn!(
+                "The user '{}' tried to access the device {} which belongs to someone else",
+                self.username, device_id
+            );
+            return Err(Status::Forbidden);
+        }
+        Ok(())
+    }
+}
+
 pub struct AuthenticatedClient {
     pub id: i32,
     pub health_callback_url: Option<String>,
+    pub encryption_key: Option<String>,
 }
 
 #[derive(Debug)]
 pub enum AuthorizationError {
     /// Could not find any authentication URL parameters in the request
     MissingAuthorizationUrlParameter,
+    /// The Authorization header is present but does not contain valid HTTP Basic credentials
+    MalformedAuthorizationHeader,
+    /// Could not find a bearer token in the Authorization header of the request
+    MissingBearerToken,
+    /// The bearer token is not valid (e.g. wrong signature, expired, wrong issuer or audience)
+    InvalidToken,
+    /// The user the token was issued for is not known (anymore)
+    UnknownUser,
+    /// The token was revoked before it expired (e.g. since the user logged out)
+    RevokedToken,
+    /// Could not find the backend configuration we need
+    BackendConfigurationNotFound,
+    /// The user is authenticated but does not have the permission required for the route
+    MissingPermission,
     /// Could not find the database connection pool we need
     DatabaseConnectionPoolNotFound,
     /// There was a generic database error which prevented to fetch information
     DatabaseError,
 }
 
+/// Get the client id and the client secret from the `Authorization: Basic` header (which is used
+/// by the OwnTracks apps in HTTP mode) or, as a fallback, from the URL parameters of the request.
+/// Authorization headers with another scheme (e.g. added by a proxy) are ignored.
+fn get_client_credentials(
+    request: &Request<'_>,
+) -> Result<Option<(String, String)>, AuthorizationError> {
+    let basic_credentials = request
+        .headers()
+        .get_one("Authorization")
+        .and_then(|authorization_header| authorization_header.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Basic"));
+    if let Some((_, encoded_credentials)) = basic_credentials {
+        let decoded_credentials = BASE64_STANDARD
+            .decode(encoded_credentials.trim())
+            .ok()
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .ok_or(AuthorizationError::MalformedAuthorizationHeader)?;
+        let Some((client_id, client_secret)) = decoded_credentials.split_once(':') else {
+            return Err(AuthorizationError::MalformedAuthorizationHeader);
+        };
+        return Ok(Some((client_id.to_string(), client_secret.to_string())));
+    }
+
+    match (
+        request.query_value::<String>("client_id"),
+        request.query_value::<String>("client_secret"),
+    ) {
+        (Some(Ok(client_id)), Some(Ok(client_secret))) => Ok(Some((client_id, client_secret))),
+        _ => Ok(None),
+    }
+}
+
+/// Remember the user and device name the OwnTracks app reported in the `X-Limit-U` and
+/// `X-Limit-D` headers, if they changed since the last request of the client. A value which was
+/// not sent with the request is kept as it is.
+fn store_reported_identity(
+    request: &Request<'_>,
+    client_token: &ClientToken,
+    db_connection: &mut PgConnection,
+) {
+    let reported_user = request
+        .headers()
+        .get_one("X-Limit-U")
+        .filter(|user| Some(*user) != client_token.reported_user.as_deref());
+    let reported_device = request
+        .headers()
+        .get_one("X-Limit-D")
+        .filter(|device| Some(*device) != client_token.reported_device.as_deref());
+    if reported_user.is_none() && reported_device.is_none() {
+        return;
+    }
+
+    if let Err(error) = diesel::update(client_tokens.find(client_token.id))
+        .set(&ReportedIdentityChange {
+            reported_user,
+            reported_device,
+        })
+        .execute(db_connection)
+    {
+        error!(
+            "Could not store the reported user and device of the client {}. The error was: {}",
+            client_token.id, error
+        );
+    }
+}
+
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for AuthenticatedClient {
     type Error = AuthorizationError;
@@ -41,81 +206,333 @@ impl<'r> FromRequest<'r> for AuthenticatedClient {
                     .ip(),
             )
             .to_string();
-        if let Some(maybe_client_id) = request.query_value::<String>("client_id") {
-            if let Some(maybe_client_secret) = request.query_value::<String>("client_secret") {
-                let client_id = maybe_client_id.unwrap(); // TODO: better handling
-                let client_secret = maybe_client_secret.unwrap(); // TODO: better handling
-
-                let db_connection_pool_state = match request
-                    .guard::<&State<ThereIWasDatabaseConnection>>()
-                    .await
+        let (client_id, client_secret) = match get_client_credentials(request) {
+            Ok(Some(credentials)) => credentials,
+            Ok(None) => {
+                warn!("Could not find the client credentials in the Authorization header or the URL parameters of the request");
+                return Outcome::Error((
+                    Status::Forbidden,
+                    AuthorizationError::MissingAuthorizationUrlParameter,
+                ));
+            }
+            Err(error) => {
+                warn!("The Authorization header of the request does not contain valid HTTP Basic credentials");
+                return Outcome::Error((Status::BadRequest, error));
+            }
+        };
+
+        let db_connection_pool_state = match request
+            .guard::<&State<ThereIWasDatabaseConnection>>()
+            .await
+        {
+            Outcome::Success(state) => state,
+            Outcome::Error(_) | Outcome::Forward(_) => {
+                error!("Failed to get database connection pool from the application managed state");
+                return Outcome::Error((
+                    Status::InternalServerError,
+                    AuthorizationError::DatabaseConnectionPoolNotFound,
+                ));
+            }
+        };
+        let mut db_connection_pool = db_connection_pool_state.get().unwrap();
+
+        match client_tokens
+            .filter(client_id_column.eq(&client_id))
+            .filter(revoked_at_column.is_null())
+            .load::<ClientToken>(&mut db_connection_pool)
+        {
+            Ok(matching_client_tokens) => {
+                // even if the client is not known, we verify the secret against a dummy hash to not
+                // leak the existence of a client id through the response time
+                let client_token = matching_client_tokens.first();
+                let stored_secret_hash = client_token
+                    .map(|client_token| client_token.secret_hash.clone())
+                    .unwrap_or(DUMMY_CLIENT_SECRET_HASH.clone());
+                // bcrypt is slow by design, so the verification must not block the async workers
+                let is_secret_correct = match rocket::tokio::task::spawn_blocking(move || {
+                    verify(&client_secret, &stored_secret_hash)
+                })
+                .await
                 {
-                    Outcome::Success(state) => state,
-                    Outcome::Error(_) | Outcome::Forward(_) => {
-                        error!("Failed to get database connection pool from the application managed state");
-                        return Outcome::Error((
-                            Status::InternalServerError,
-                            AuthorizationError::DatabaseConnectionPoolNotFound,
-                        ));
+                    Ok(Ok(is_secret_correct)) => is_secret_correct,
+                    Ok(Err(error)) => {
+                        error!("Could not verify the supplied client secret with the one stored in the database. The error was: {}", error);
+                        false
+                    }
+                    Err(error) => {
+                        error!("The verification of the supplied client secret failed. The error was: {}", error);
+                        false
                     }
                 };
-                let mut db_connection_pool = db_connection_pool_state.get().unwrap();
-
-                match client_tokens
-                    .filter(
-                        client_id_column
-                            .eq(&client_id)
-                            .and(client_secret_column.eq(client_secret)),
-                    )
-                    .load::<ClientToken>(&mut db_connection_pool)
-                {
-                    Ok(matching_client_tokens) => {
-                        if let Some(client_token) = matching_client_tokens.first() {
-                            debug!("Successfully found valid token. Authenticating client with the id {}", client_token.id);
-                            log_audit_message(
-                                &mut db_connection_pool,
-                                AuditLogAction::ClientTokenAuthentication,
-                                AuditLogResult::Successful,
-                                &remote_endppoint,
-                            );
-                            return Outcome::Success(AuthenticatedClient {
-                                id: client_token.id,
-                                health_callback_url: client_token.health_callback_url.clone(),
-                            });
-                        }
-                        warn!("Could not find a matching client_id and client_secret pair in the database");
-                        log_audit_message(
-                            &mut db_connection_pool,
-                            AuditLogAction::ClientTokenAuthentication,
-                            AuditLogResult::Failed,
-                            &remote_endppoint,
-                        );
-                        return Outcome::Error((
-                            Status::Forbidden,
-                            AuthorizationError::MissingAuthorizationUrlParameter,
-                        ));
+                if let Some(client_token) = client_token.filter(|_| is_secret_correct) {
+                    debug!(
+                        "Successfully found valid token. Authenticating client with the id {}",
+                        client_token.id
+                    );
+                    log_audit_message(
+                        &mut db_connection_pool,
+                        AuditLogAction::ClientTokenAuthentication,
+                        AuditLogResult::Successful,
+                        &remote_endppoint,
+                    );
+                    store_reported_identity(request, client_token, &mut db_connection_pool);
+                    return Outcome::Success(AuthenticatedClient {
+                        id: client_token.id,
+                        health_callback_url: client_token.health_callback_url.clone(),
+                        encryption_key: client_token.encryption_key.clone(),
+                    });
+                }
+                warn!("Could not find a matching client_id and client_secret pair in the database");
+                log_audit_message(
+                    &mut db_connection_pool,
+                    AuditLogAction::ClientTokenAuthentication,
+                    AuditLogResult::Failed,
+                    &remote_endppoint,
+                );
+                Outcome::Error((
+                    Status::Forbidden,
+                    AuthorizationError::MissingAuthorizationUrlParameter,
+                ))
+            }
+            Err(e) => {
+                error!("Failed to query the client token for the client with the id of {}. The error was: {}", client_id, e);
+                log_audit_message(
+                    &mut db_connection_pool,
+                    AuditLogAction::ClientTokenAuthentication,
+                    AuditLogResult::Failed,
+                    &remote_endppoint,
+                );
+                Outcome::Error((
+                    Status::InternalServerError,
+                    AuthorizationError::DatabaseError,
+                ))
+            }
+        }
+    }
+}
+
+/// Get the names of all permissions which are granted to the user through the assigned roles.
+fn get_permissions_for_user(
+    user_id: i32,
+    db_connection: &mut PgConnection,
+) -> Result<Vec<String>, diesel::result::Error> {
+    use crate::schema::permissions::dsl::permissions;
+    use crate::schema::roles_to_permissions::dsl::roles_to_permissions;
+    use crate::schema::users_to_roles as user_role;
+    use crate::schema::users_to_roles::dsl::users_to_roles;
+    use crate::schema::{permissions as permission, roles_to_permissions as role_permission};
+
+    users_to_roles
+        .inner_join(roles_to_permissions.on(role_permission::role_id.eq(user_role::role_id)))
+        .inner_join(permissions.on(permission::id.eq(role_permission::permission_id)))
+        .filter(user_role::user_id.eq(user_id))
+        .select(permission::name)
+        .distinct()
+        .load::<String>(db_connection)
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthenticatedUser {
+    type Error = AuthorizationError;
+
+    async fn from_request(
+        request: &'r Request<'_>,
+    ) -> Outcome<AuthenticatedUser, AuthorizationError> {
+        use jsonwebtoken::{decode, Algorithm, Validation};
+
+        let Some(token) = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Bearer"))
+            .map(|(_, token)| token.trim())
+        else {
+            debug!("Could not find a bearer token in the Authorization header of the request");
+            return Outcome::Error((Status::Unauthorized, AuthorizationError::MissingBearerToken));
+        };
+
+        let backend_config = match request.guard::<&State<BackendConfiguration>>().await {
+            Outcome::Success(config) => config,
+            Outcome::Error(_) | Outcome::Forward(_) => {
+                error!(
+                    "Failed to get the backend configuration from the application managed state"
+                );
+                return Outcome::Error((
+                    Status::InternalServerError,
+                    AuthorizationError::BackendConfigurationNotFound,
+                ));
+            }
+        };
+        let Some(decoding_key) = backend_config.decoding_key.as_ref() else {
+            error!("There is no key configured which can be used to validate the token signatures");
+            return Outcome::Error((
+                Status::InternalServerError,
+                AuthorizationError::BackendConfigurationNotFound,
+            ));
+        };
+
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.validate_nbf = true;
+        validation.set_issuer(&[&backend_config.api_host]);
+        validation.set_audience(&backend_config.token_audience.iter().collect::<Vec<_>>());
+        validation.set_required_spec_claims(&["exp", "nbf", "sub", "iss", "aud"]);
+        let claims = match decode::<Claims>(token.trim(), decoding_key, &validation) {
+            Ok(token_data) => token_data.claims,
+            Err(error) => {
+                warn!(
+                    "The supplied bearer token is not valid. The error was: {}",
+                    error
+                );
+                return Outcome::Error((Status::Unauthorized, AuthorizationError::InvalidToken));
+            }
+        };
+
+        let db_connection_pool_state = match request
+            .guard::<&State<ThereIWasDatabaseConnection>>()
+            .await
+        {
+            Outcome::Success(state) => state,
+            Outcome::Error(_) | Outcome::Forward(_) => {
+                error!("Failed to get database connection pool from the application managed state");
+                return Outcome::Error((
+                    Status::InternalServerError,
+                    AuthorizationError::DatabaseConnectionPoolNotFound,
+                ));
+            }
+        };
+        let Ok(mut db_connection) = db_connection_pool_state.get() else {
+            error!("Could not get a database connection from the connection pool");
+            return Outcome::Error((
+                Status::ServiceUnavailable,
+                AuthorizationError::DatabaseError,
+            ));
+        };
+
+        match revoked_access_tokens
+            .filter(token_id_column.eq(&claims.jti))
+            .count()
+            .get_result::<i64>(&mut db_connection)
+        {
+            Ok(0) => {}
+            Ok(_) => {
+                warn!(
+                    "The user '{}' tried to use an access token which was already revoked",
+                    claims.sub
+                );
+                return Outcome::Error((Status::Unauthorized, AuthorizationError::RevokedToken));
+            }
+            Err(error) => {
+                error!(
+                    "Failed to check if the token of the user '{}' was revoked. The error was: {}",
+                    claims.sub, error
+                );
+                return Outcome::Error((
+                    Status::InternalServerError,
+                    AuthorizationError::DatabaseError,
+                ));
+            }
+        }
+
+        match users
+            .filter(username_column.eq(&claims.sub))
+            .first::<User>(&mut db_connection)
+            .optional()
+        {
+            Ok(Some(user)) => match get_permissions_for_user(user.id, &mut db_connection) {
+                Ok(permissions) => Outcome::Success(AuthenticatedUser {
+                    id: user.id,
+                    username: user.username,
+                    permissions,
+                    token_id: claims.jti,
+                    token_expires_at: DateTime::from_timestamp(claims.exp as i64, 0)
+                        .unwrap_or_default()
+                        .naive_utc(),
+                }),
+                Err(error) => {
+                    error!(
+                        "Failed to query the permissions of the user '{}'. The error was: {}",
+                        claims.sub, error
+                    );
+                    Outcome::Error((
+                        Status::InternalServerError,
+                        AuthorizationError::DatabaseError,
+                    ))
+                }
+            },
+            Ok(None) => {
+                warn!(
+                    "Received a valid token for the user '{}' which is not known",
+                    claims.sub
+                );
+                Outcome::Error((Status::Unauthorized, AuthorizationError::UnknownUser))
+            }
+            Err(error) => {
+                error!(
+                    "Failed to query the user '{}' for the supplied token. The error was: {}",
+                    claims.sub, error
+                );
+                Outcome::Error((
+                    Status::InternalServerError,
+                    AuthorizationError::DatabaseError,
+                ))
+            }
+        }
+    }
+}
+
+/// Define a request guard which only succeeds if the authenticated user has the supplied
+/// permission. If the permission is missing, the request is answered with 403 Forbidden.
+macro_rules! permission_guard {
+    ($(#[$meta:meta])* $name:ident, $permission:literal) => {
+        $(#[$meta])*
+        pub struct $name(pub AuthenticatedUser);
+
+        #[rocket::async_trait]
+        impl<'r> FromRequest<'r> for $name {
+            type Error = AuthorizationError;
+
+            async fn from_request(request: &'r Request<'_>) -> Outcome<$name, AuthorizationError> {
+                match request.guard::<AuthenticatedUser>().await {
+                    Outcome::Success(user) if user.has_permission($permission) => {
+                        Outcome::Success($name(user))
                     }
-                    Err(e) => {
-                        error!("Failed to query the client token for the client with the id of {}. The error was: {}", client_id, e);
-                        log_audit_message(
-                            &mut db_connection_pool,
-                            AuditLogAction::ClientTokenAuthentication,
-                            AuditLogResult::Failed,
-                            &remote_endppoint,
+                    Outcome::Success(user) => {
+                        warn!(
+                            "The user '{}' tried to access a route which requires the '{}' permission",
+                            user.username, $permission
                         );
-                        return Outcome::Error((
-                            Status::InternalServerError,
-                            AuthorizationError::DatabaseError,
-                        ));
+                        Outcome::Error((Status::Forbidden, AuthorizationError::MissingPermission))
                     }
+                    Outcome::Error(error) => Outcome::Error(error),
+                    Outcome::Forward(status) => Outcome::Forward(status),
                 }
             }
         }
-
-        warn!("Could not find the client_id or the client_secret in the URL parameters of the request");
-        Outcome::Error((
-            Status::Forbidden,
-            AuthorizationError::MissingAuthorizationUrlParameter,
-        ))
-    }
+    };
 }
+
+permission_guard!(
+    /// A user which is allowed to list all users registered to this instance.
+    ViewUsersPermission,
+    "view:users"
+);
+permission_guard!(
+    /// A user which is allowed to create new users on this instance.
+    CreateUsersPermission,
+    "create:users"
+);
+permission_guard!(
+    /// A user which is allowed to change the roles of the users of this instance.
+    EditUsersPermission,
+    "edit:users"
+);
+permission_guard!(
+    /// A user which is allowed to delete users from this instance.
+    DeleteUsersPermission,
+    "delete:users"
+);
+permission_guard!(
+    /// A user which is allowed to register, change and revoke the devices of this instance.
+    ManageDevicesPermission,
+    "manage:devices"
+);
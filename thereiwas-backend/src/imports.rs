@@ -0,0 +1,121 @@
+use crate::models::NewLocation;
+use crate::routes::owntracks::ReportTrigger;
+use chrono::{DateTime, NaiveDateTime};
+
+pub mod geojson;
+pub mod gpx;
+pub mod kml;
+pub mod takeout;
+
+/// A location which was read from a location history of another app.
+pub struct ImportedLocation {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub measurement_time: NaiveDateTime,
+    pub horizontal_accuracy: Option<i32>,
+    pub vertical_accuracy: Option<i32>,
+    pub altitude: Option<i32>,
+    pub barometric_pressure: Option<f64>,
+    /// The velocity in km/h (like it is reported by OwnTracks).
+    pub velocity: Option<i32>,
+    pub course_over_ground: Option<i32>,
+    /// The BSSID of the WiFi access point the device was connected to.
+    pub wifi_bssid: Option<String>,
+}
+
+impl ImportedLocation {
+    /// Get a location of which only the coordinates and the measurement time are known.
+    pub fn new(latitude: f64, longitude: f64, measurement_time: NaiveDateTime) -> Self {
+        ImportedLocation {
+            latitude,
+            longitude,
+            measurement_time,
+            horizontal_accuracy: None,
+            vertical_accuracy: None,
+            altitude: None,
+            barometric_pressure: None,
+            velocity: None,
+            course_over_ground: None,
+            wifi_bssid: None,
+        }
+    }
+
+    pub fn into_new_location(self, reporting_device: i32) -> NewLocation {
+        NewLocation {
+            horizontal_accuracy: self.horizontal_accuracy,
+            altitude: self.altitude,
+            latitude: self.latitude,
+            longitude: self.longitude,
+            report_trigger: ReportTrigger::Import.to_string(),
+            measurement_time: self.measurement_time,
+            vertical_accuracy: self.vertical_accuracy,
+            barometric_pressure: self.barometric_pressure,
+            created_at: None,
+            reporting_device,
+            battery_level: None,
+            battery_status: None,
+            monitoring_mode: None,
+            velocity: self.velocity,
+            course_over_ground: self.course_over_ground,
+            connection_type: self.wifi_bssid.as_ref().map(|_| "w".to_string()),
+            topic: None,
+            in_regions: None,
+            in_region_ids: None,
+        }
+    }
+}
+
+/// The locations which could be read from a location history and the number of entries which
+/// were rejected since they did not contain a valid location.
+#[derive(Default)]
+pub struct ParsedImport {
+    pub locations: Vec<ImportedLocation>,
+    pub rejected_locations: usize,
+}
+
+impl ParsedImport {
+    /// Add the location or count it as rejected if it is not a valid location.
+    fn push(&mut self, location: Option<ImportedLocation>) {
+        match location {
+            Some(location) if is_valid_coordinate(location.latitude, location.longitude) => {
+                self.locations.push(location)
+            }
+            _ => self.rejected_locations += 1,
+        }
+    }
+}
+
+/// Parse the track file (GPX, KML or GeoJSON) and return the locations it contains. The format
+/// is detected from the content of the file.
+pub fn parse_track_file(track_file: &[u8]) -> Result<ParsedImport, String> {
+    let track_file = std::str::from_utf8(track_file).map_err(|error| error.to_string())?;
+    let track_file = track_file.trim_start_matches('\u{feff}').trim_start();
+    if track_file.starts_with('{') {
+        return geojson::parse_geojson_file(track_file);
+    }
+
+    let document = roxmltree::Document::parse(track_file).map_err(|error| error.to_string())?;
+    match document.root_element().tag_name().name() {
+        "gpx" => Ok(gpx::parse_gpx_document(&document)),
+        "kml" => Ok(kml::parse_kml_document(&document)),
+        root_element => Err(format!(
+            "The XML file with the root element '{}' is neither a GPX nor a KML file",
+            root_element
+        )),
+    }
+}
+
+fn parse_time(time: &str) -> Option<NaiveDateTime> {
+    DateTime::parse_from_rfc3339(time.trim())
+        .ok()
+        .map(|time| time.naive_utc())
+}
+
+fn is_valid_coordinate(latitude: f64, longitude: f64) -> bool {
+    (-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude)
+}
+
+/// Convert a speed in m/s to km/h, the unit OwnTracks uses for the velocity.
+fn get_velocity(meters_per_second: f64) -> i32 {
+    (meters_per_second * 3.6).round() as i32
+}
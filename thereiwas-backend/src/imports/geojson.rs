@@ -0,0 +1,180 @@
+use super::{parse_time, ImportedLocation, ParsedImport};
+use chrono::{DateTime, NaiveDateTime};
+use serde_json::{Map, Value};
+
+/// The names of the properties which can contain the time of a point, either as seconds since the
+/// epoch or as RFC 3339 string. `measurement_time` is used by the GeoJSON positions.
+const TIME_PROPERTIES: [&str; 3] = ["measurement_time", "time", "timestamp"];
+
+fn parse_time_value(time: &Value) -> Option<NaiveDateTime> {
+    match time {
+        Value::String(time) => parse_time(time),
+        time => Some(DateTime::from_timestamp(time.as_i64()?, 0)?.naive_utc()),
+    }
+}
+
+fn get_integer_property(properties: &Map<String, Value>, name: &str) -> Option<i32> {
+    properties
+        .get(name)
+        .and_then(Value::as_f64)
+        .map(|value| value.round() as i32)
+}
+
+fn get_location(coordinate: &Value, time: Option<&Value>) -> Option<ImportedLocation> {
+    let coordinate = coordinate.as_array()?;
+    let longitude = coordinate.first()?.as_f64()?;
+    let latitude = coordinate.get(1)?.as_f64()?;
+    Some(ImportedLocation {
+        altitude: coordinate
+            .get(2)
+            .and_then(Value::as_f64)
+            .map(|altitude| altitude.round() as i32),
+        ..ImportedLocation::new(latitude, longitude, parse_time_value(time?)?)
+    })
+}
+
+/// Get the locations of a line, the times of the coordinates are expected in the `coordTimes`
+/// property (like it is written by most converters).
+fn push_line(parsed_import: &mut ParsedImport, coordinates: &[Value], times: Option<&Value>) {
+    let times = times.and_then(Value::as_array);
+    for (index, coordinate) in coordinates.iter().enumerate() {
+        let time = times.and_then(|times| times.get(index));
+        parsed_import.push(get_location(coordinate, time));
+    }
+}
+
+fn push_geometry(
+    parsed_import: &mut ParsedImport,
+    geometry: &Value,
+    properties: &Map<String, Value>,
+) {
+    let coordinates = geometry.get("coordinates");
+    let get_coordinate_list = |coordinates: Option<&Value>| {
+        coordinates
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default()
+    };
+
+    match geometry.get("type").and_then(Value::as_str) {
+        Some("Point") => {
+            let time = TIME_PROPERTIES
+                .iter()
+                .find_map(|name| properties.get(*name));
+            let location = coordinates
+                .and_then(|coordinate| get_location(coordinate, time))
+                .map(|location| ImportedLocation {
+                    horizontal_accuracy: get_integer_property(properties, "horizontal_accuracy"),
+                    vertical_accuracy: get_integer_property(properties, "vertical_accuracy"),
+                    barometric_pressure: properties
+                        .get("barometric_pressure")
+                        .and_then(Value::as_f64),
+                    velocity: get_integer_property(properties, "velocity"),
+                    course_over_ground: get_integer_property(properties, "course_over_ground"),
+                    ..location
+                });
+            parsed_import.push(location);
+        }
+        Some("MultiPoint") | Some("LineString") => push_line(
+            parsed_import,
+            &get_coordinate_list(coordinates),
+            properties.get("coordTimes"),
+        ),
+        Some("MultiLineString") => {
+            let times = properties.get("coordTimes").and_then(Value::as_array);
+            for (index, line) in get_coordinate_list(coordinates).iter().enumerate() {
+                push_line(
+                    parsed_import,
+                    &get_coordinate_list(Some(line)),
+                    times.and_then(|times| times.get(index)),
+                );
+            }
+        }
+        Some("GeometryCollection") => {
+            for geometry in get_coordinate_list(geometry.get("geometries")) {
+                push_geometry(parsed_import, &geometry, properties);
+            }
+        }
+        // areas are not locations a device was at
+        _ => {}
+    }
+}
+
+fn push_object(parsed_import: &mut ParsedImport, object: &Value) {
+    match object.get("type").and_then(Value::as_str) {
+        Some("FeatureCollection") => {
+            for feature in object
+                .get("features")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+            {
+                push_object(parsed_import, feature);
+            }
+        }
+        Some("Feature") => {
+            let properties = object
+                .get("properties")
+                .and_then(Value::as_object)
+                .cloned()
+                .unwrap_or_default();
+            if let Some(geometry) = object.get("geometry") {
+                push_geometry(parsed_import, geometry, &properties);
+            }
+        }
+        _ => push_geometry(parsed_import, object, &Map::new()),
+    }
+}
+
+/// Get the locations of the points and lines of a GeoJSON file. Coordinates without a time are
+/// rejected since the measurement time of a location is required.
+pub fn parse_geojson_file(geojson_file: &str) -> Result<ParsedImport, String> {
+    let geojson: Value = serde_json::from_str(geojson_file).map_err(|error| error.to_string())?;
+    let mut parsed_import = ParsedImport::default();
+    push_object(&mut parsed_import, &geojson);
+    Ok(parsed_import)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_geojson_points_and_lines_are_parsed() {
+        let parsed_import = parse_geojson_file(
+            r#"{"type": "FeatureCollection", "features": [
+                {"type": "Feature", "geometry": {"type": "Point", "coordinates": [6.7829, 51.2353, 38]},
+                 "properties": {"measurement_time": 1700000000, "horizontal_accuracy": 12, "velocity": 5}},
+                {"type": "Feature", "geometry": {"type": "Point", "coordinates": [6.8, 51.25]}, "properties": {"name": "Home"}},
+                {"type": "Feature", "geometry": {"type": "LineString", "coordinates": [[6.7829, 51.2353], [6.7839, 51.2363], [6.7849, 51.2373]]},
+                 "properties": {"coordTimes": ["2023-11-14T22:14:20Z", "2023-11-14T22:15:20Z"]}},
+                {"type": "Feature", "geometry": {"type": "Polygon", "coordinates": [[[6.7, 51.2], [6.8, 51.2], [6.8, 51.3], [6.7, 51.2]]]}, "properties": null}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(parsed_import.locations.len(), 3);
+        assert_eq!(parsed_import.rejected_locations, 2);
+
+        let location = &parsed_import.locations[0];
+        assert_eq!(
+            (location.latitude, location.longitude, location.altitude),
+            (51.2353, 6.7829, Some(38))
+        );
+        assert_eq!(
+            location.measurement_time.and_utc().timestamp(),
+            1_700_000_000
+        );
+        assert_eq!(
+            (location.horizontal_accuracy, location.velocity),
+            (Some(12), Some(5))
+        );
+        assert_eq!(
+            parsed_import.locations[2]
+                .measurement_time
+                .and_utc()
+                .timestamp(),
+            1_700_000_120
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use super::{parse_time, ImportedLocation, ParsedImport};
+use roxmltree::{Document, Node};
+
+/// The namespace of the GPX extension elements which are written by the GPX export.
+const GPX_EXTENSION_NAMESPACE: &str = "https://github.com/flying7eleven/thereiwas/gpx/1";
+
+fn get_child_text<'a>(node: &Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|child| child.tag_name().name() == name)
+        .and_then(|child| child.text())
+}
+
+fn get_extension_value<T: std::str::FromStr>(node: &Node, name: &str) -> Option<T> {
+    node.descendants()
+        .find(|child| {
+            child.tag_name().namespace() == Some(GPX_EXTENSION_NAMESPACE)
+                && child.tag_name().name() == name
+        })
+        .and_then(|child| child.text())
+        .and_then(|value| value.trim().parse().ok())
+}
+
+fn get_point_location(point: &Node) -> Option<ImportedLocation> {
+    let latitude = point.attribute("lat")?.trim().parse().ok()?;
+    let longitude = point.attribute("lon")?.trim().parse().ok()?;
+    let measurement_time = parse_time(get_child_text(point, "time")?)?;
+
+    Some(ImportedLocation {
+        altitude: get_child_text(point, "ele")
+            .and_then(|elevation| elevation.trim().parse::<f64>().ok())
+            .map(|elevation| elevation.round() as i32),
+        horizontal_accuracy: get_extension_value(point, "horizontal_accuracy"),
+        vertical_accuracy: get_extension_value(point, "vertical_accuracy"),
+        barometric_pressure: get_extension_value(point, "barometric_pressure"),
+        ..ImportedLocation::new(latitude, longitude, measurement_time)
+    })
+}
+
+/// Get the locations of the track, route and way points of a GPX document. Points without a time
+/// are rejected since the measurement time of a location is required.
+pub fn parse_gpx_document(document: &Document) -> ParsedImport {
+    let mut parsed_import = ParsedImport::default();
+    for point in document
+        .descendants()
+        .filter(|node| matches!(node.tag_name().name(), "trkpt" | "rtept" | "wpt"))
+    {
+        parsed_import.push(get_point_location(&point));
+    }
+    parsed_import
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gpx_points_are_parsed_with_the_extensions_of_the_export() {
+        let document = Document::parse(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <gpx version="1.1" xmlns="http://www.topografix.com/GPX/1/1" xmlns:thereiwas="https://github.com/flying7eleven/thereiwas/gpx/1">
+              <wpt lat="51.25" lon="6.8"><name>Home</name></wpt>
+              <trk><trkseg>
+                <trkpt lat="51.2353" lon="6.7829"><ele>38.4</ele><time>2023-11-14T22:13:20Z</time><extensions><thereiwas:horizontal_accuracy>12</thereiwas:horizontal_accuracy><thereiwas:barometric_pressure>101.3</thereiwas:barometric_pressure></extensions></trkpt>
+                <trkpt lat="91.0" lon="6.7829"><time>2023-11-14T22:14:20Z</time></trkpt>
+                <trkpt lat="51.2363" lon="6.7839"><time>2023-11-14T23:14:20+01:00</time></trkpt>
+              </trkseg></trk>
+            </gpx>"#,
+        )
+        .unwrap();
+
+        let parsed_import = parse_gpx_document(&document);
+        assert_eq!(parsed_import.locations.len(), 2);
+        assert_eq!(parsed_import.rejected_locations, 2);
+
+        let location = &parsed_import.locations[0];
+        assert_eq!((location.latitude, location.longitude), (51.2353, 6.7829));
+        assert_eq!(
+            location.measurement_time.and_utc().timestamp(),
+            1_700_000_000
+        );
+        assert_eq!(
+            (
+                location.altitude,
+                location.horizontal_accuracy,
+                location.barometric_pressure
+            ),
+            (Some(38), Some(12), Some(101.3))
+        );
+        assert_eq!(
+            parsed_import.locations[1]
+                .measurement_time
+                .and_utc()
+                .timestamp(),
+            1_700_000_060
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use super::{parse_time, ImportedLocation, ParsedImport};
+use roxmltree::{Document, Node};
+
+/// Parse the longitude, latitude and (optional) altitude of a KML coordinate. The values of a
+/// `coordinates` element are separated by commas, the ones of a `gx:coord` element by spaces.
+fn parse_coordinate(coordinate: &str) -> Option<(f64, f64, Option<i32>)> {
+    let mut values = coordinate
+        .split([',', ' '])
+        .filter(|value| !value.is_empty())
+        .map(|value| value.parse::<f64>());
+    let longitude = values.next()?.ok()?;
+    let latitude = values.next()?.ok()?;
+    let altitude = values
+        .next()
+        .and_then(|altitude| altitude.ok())
+        .map(|altitude| altitude.round() as i32);
+    Some((longitude, latitude, altitude))
+}
+
+fn get_location(coordinate: Option<&str>, time: Option<&str>) -> Option<ImportedLocation> {
+    let (longitude, latitude, altitude) = parse_coordinate(coordinate?.trim())?;
+    Some(ImportedLocation {
+        altitude,
+        ..ImportedLocation::new(latitude, longitude, parse_time(time?)?)
+    })
+}
+
+fn get_children<'a, 'input>(node: &Node<'a, 'input>, name: &str) -> Vec<Node<'a, 'input>> {
+    node.children()
+        .filter(|child| child.tag_name().name() == name)
+        .collect()
+}
+
+/// The time of a placemark, either of a time stamp or the beginning of a time span.
+fn get_placemark_time<'a>(placemark: &Node<'a, '_>) -> Option<&'a str> {
+    placemark
+        .children()
+        .find(|child| matches!(child.tag_name().name(), "TimeStamp" | "TimeSpan"))
+        .and_then(|time| {
+            time.children()
+                .find(|child| matches!(child.tag_name().name(), "when" | "begin"))
+        })
+        .and_then(|when| when.text())
+}
+
+/// Get the locations of a KML document. The locations of tracks (`gx:Track`) carry their time,
+/// points use the time of their placemark. The coordinates of lines have no time and are
+/// therefore rejected.
+pub fn parse_kml_document(document: &Document) -> ParsedImport {
+    let mut parsed_import = ParsedImport::default();
+
+    for track in document
+        .descendants()
+        .filter(|node| node.tag_name().name() == "Track")
+    {
+        let times = get_children(&track, "when");
+        let coordinates = get_children(&track, "coord");
+        for index in 0..times.len().max(coordinates.len()) {
+            parsed_import.push(get_location(
+                coordinates
+                    .get(index)
+                    .and_then(|coordinate| coordinate.text()),
+                times.get(index).and_then(|time| time.text()),
+            ));
+        }
+    }
+
+    for placemark in document
+        .descendants()
+        .filter(|node| node.tag_name().name() == "Placemark")
+    {
+        let time = get_placemark_time(&placemark);
+        for geometry in placemark.descendants() {
+            let coordinates = geometry
+                .children()
+                .find(|child| child.tag_name().name() == "coordinates")
+                .and_then(|coordinates| coordinates.text())
+                .unwrap_or_default();
+            match geometry.tag_name().name() {
+                "Point" => parsed_import.push(get_location(Some(coordinates), time)),
+                "LineString" | "LinearRing" => {
+                    parsed_import.rejected_locations += coordinates.split_whitespace().count()
+                }
+                _ => {}
+            }
+        }
+    }
+
+    parsed_import
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kml_tracks_and_points_are_parsed() {
+        let document = Document::parse(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2"><Document>
+              <Placemark><name>2023-11-14</name><LineString><coordinates>6.7829,51.2353,38 6.7839,51.2363</coordinates></LineString></Placemark>
+              <Placemark><name>2023-11-14 (timeline)</name><gx:Track>
+                <when>2023-11-14T22:13:20Z</when><when>2023-11-14T22:14:20Z</when><when>2023-11-14T22:15:20Z</when>
+                <gx:coord>6.7829 51.2353 38</gx:coord><gx:coord>6.7839 51.2363 0</gx:coord>
+              </gx:Track></Placemark>
+              <Placemark><name>Home</name><TimeStamp><when>2023-11-14T22:14:20Z</when></TimeStamp><Point><coordinates>6.7839,51.2363</coordinates></Point></Placemark>
+              <Placemark><name>Somewhere</name><Point><coordinates>6.8,51.25</coordinates></Point></Placemark>
+            </Document></kml>"#,
+        )
+        .unwrap();
+
+        let parsed_import = parse_kml_document(&document);
+        assert_eq!(parsed_import.locations.len(), 3);
+        // the coordinates of the line, the time without a coordinate and the point without a time
+        assert_eq!(parsed_import.rejected_locations, 4);
+
+        let location = &parsed_import.locations[0];
+        assert_eq!(
+            (location.latitude, location.longitude, location.altitude),
+            (51.2353, 6.7829, Some(38))
+        );
+        assert_eq!(
+            location.measurement_time.and_utc().timestamp(),
+            1_700_000_000
+        );
+        assert_eq!(
+            parsed_import.locations[2]
+                .measurement_time
+                .and_utc()
+                .timestamp(),
+            1_700_000_060
+        );
+    }
+}
@@ -0,0 +1,345 @@
+use super::{get_velocity, parse_time, ImportedLocation, ParsedImport};
+use chrono::DateTime;
+use serde::Deserialize;
+use serde_json::Value;
+
+/// The coordinates of `Records.json` are stored as integers (degrees multiplied by 10^7).
+const E7_FACTOR: f64 = 10_000_000.0;
+
+/// A location history which was exported with Google Takeout (`Records.json`) or from the
+/// Timeline on the device (`Timeline.json`). Only the fields of the file type are set.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TakeoutFile {
+    locations: Option<Vec<Record>>,
+    semantic_segments: Option<Vec<SemanticSegment>>,
+    raw_signals: Option<Vec<RawSignal>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Record {
+    latitude_e7: Option<i64>,
+    longitude_e7: Option<i64>,
+    accuracy: Option<i32>,
+    altitude: Option<i32>,
+    vertical_accuracy: Option<i32>,
+    /// The velocity in m/s.
+    velocity: Option<i32>,
+    heading: Option<i32>,
+    timestamp: Option<String>,
+    /// The measurement time in milliseconds since the epoch, used by older exports.
+    timestamp_ms: Option<String>,
+    active_wifi_scan: Option<WifiScan>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WifiScan {
+    #[serde(default)]
+    access_points: Vec<WifiScanAccessPoint>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WifiScanAccessPoint {
+    /// The MAC address as a decimal number, either as string or as number.
+    mac: Value,
+    #[serde(default)]
+    is_connected: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SemanticSegment {
+    start_time: Option<String>,
+    end_time: Option<String>,
+    #[serde(default)]
+    timeline_path: Vec<TimelinePathPoint>,
+    visit: Option<Visit>,
+    activity: Option<Activity>,
+}
+
+#[derive(Deserialize)]
+struct TimelinePathPoint {
+    point: Option<String>,
+    time: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Visit {
+    top_candidate: Option<VisitCandidate>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VisitCandidate {
+    place_location: Option<Place>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Place {
+    lat_lng: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Activity {
+    start: Option<Place>,
+    end: Option<Place>,
+}
+
+/// Besides positions, the raw signals contain WiFi scans and activities which are not imported.
+#[derive(Deserialize)]
+struct RawSignal {
+    position: Option<RawPosition>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawPosition {
+    #[serde(rename = "LatLng")]
+    lat_lng: Option<String>,
+    accuracy_meters: Option<f64>,
+    altitude_meters: Option<f64>,
+    speed_meters_per_second: Option<f64>,
+    timestamp: Option<String>,
+}
+
+/// Parse coordinates in the format of `Timeline.json` (e.g. `51.2353°, 6.7829°`).
+fn parse_lat_lng(lat_lng: &str) -> Option<(f64, f64)> {
+    let (latitude, longitude) = lat_lng.split_once(',')?;
+    let parse_degrees = |degrees: &str| degrees.trim().trim_end_matches('°').parse::<f64>().ok();
+    Some((parse_degrees(latitude)?, parse_degrees(longitude)?))
+}
+
+/// Get the degrees of a coordinate of `Records.json`. Some exports contain coordinates which
+/// overflowed a signed 32-bit integer, which have to be corrected.
+fn get_degrees_from_e7(e7: i64) -> f64 {
+    let e7 = if e7 > i32::MAX as i64 {
+        e7 - (1_i64 << 32)
+    } else {
+        e7
+    };
+    e7 as f64 / E7_FACTOR
+}
+
+/// Get the BSSID (e.g. `DE:AD:BE:EF:00:00`) of a MAC address which is stored as decimal number.
+fn get_bssid(mac: &Value) -> Option<String> {
+    let mac = match mac {
+        Value::String(mac) => mac.parse::<u64>().ok()?,
+        mac => mac.as_u64()?,
+    };
+    let bssid = mac.to_be_bytes()[2..]
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":");
+    Some(bssid)
+}
+
+fn get_record_location(record: Record) -> Option<ImportedLocation> {
+    let measurement_time = match (&record.timestamp, &record.timestamp_ms) {
+        (Some(timestamp), _) => parse_time(timestamp)?,
+        (None, Some(timestamp_ms)) => {
+            DateTime::from_timestamp_millis(timestamp_ms.parse().ok()?)?.naive_utc()
+        }
+        (None, None) => return None,
+    };
+
+    // only the access point the device was connected to is associated with the location
+    let wifi_bssid = record.active_wifi_scan.and_then(|wifi_scan| {
+        wifi_scan
+            .access_points
+            .iter()
+            .find(|access_point| access_point.is_connected)
+            .and_then(|access_point| get_bssid(&access_point.mac))
+    });
+
+    Some(ImportedLocation {
+        horizontal_accuracy: record.accuracy,
+        vertical_accuracy: record.vertical_accuracy,
+        altitude: record.altitude,
+        velocity: record
+            .velocity
+            .map(|velocity| get_velocity(velocity as f64)),
+        course_over_ground: record.heading,
+        wifi_bssid,
+        ..ImportedLocation::new(
+            get_degrees_from_e7(record.latitude_e7?),
+            get_degrees_from_e7(record.longitude_e7?),
+            measurement_time,
+        )
+    })
+}
+
+fn get_timeline_location(lat_lng: Option<&str>, time: Option<&str>) -> Option<ImportedLocation> {
+    let (latitude, longitude) = parse_lat_lng(lat_lng?)?;
+    Some(ImportedLocation::new(
+        latitude,
+        longitude,
+        parse_time(time?)?,
+    ))
+}
+
+fn get_raw_position_location(position: RawPosition) -> Option<ImportedLocation> {
+    let (latitude, longitude) = parse_lat_lng(position.lat_lng.as_deref()?)?;
+    Some(ImportedLocation {
+        horizontal_accuracy: position
+            .accuracy_meters
+            .map(|accuracy| accuracy.round() as i32),
+        altitude: position
+            .altitude_meters
+            .map(|altitude| altitude.round() as i32),
+        velocity: position.speed_meters_per_second.map(get_velocity),
+        ..ImportedLocation::new(
+            latitude,
+            longitude,
+            parse_time(position.timestamp.as_deref()?)?,
+        )
+    })
+}
+
+/// Parse a `Records.json` or `Timeline.json` file. Entries without a valid location are counted
+/// as rejected, an error is only returned if the file is not one of the supported files.
+pub fn parse_takeout_file(takeout_file: &[u8]) -> Result<ParsedImport, String> {
+    let takeout_file: TakeoutFile =
+        serde_json::from_slice(takeout_file).map_err(|error| error.to_string())?;
+    if takeout_file.locations.is_none()
+        && takeout_file.semantic_segments.is_none()
+        && takeout_file.raw_signals.is_none()
+    {
+        return Err("The file is neither a Records.json nor a Timeline.json file".to_string());
+    }
+
+    let mut parsed_import = ParsedImport::default();
+    for record in takeout_file.locations.unwrap_or_default() {
+        parsed_import.push(get_record_location(record));
+    }
+
+    // the visits and activities of the semantic segments are imported with the time they
+    // started and ended at
+    for segment in takeout_file.semantic_segments.unwrap_or_default() {
+        for point in &segment.timeline_path {
+            parsed_import.push(get_timeline_location(
+                point.point.as_deref(),
+                point.time.as_deref(),
+            ));
+        }
+        if let Some(visit) = segment.visit {
+            let lat_lng = visit
+                .top_candidate
+                .and_then(|candidate| candidate.place_location)
+                .and_then(|place| place.lat_lng);
+            parsed_import.push(get_timeline_location(
+                lat_lng.as_deref(),
+                segment.start_time.as_deref(),
+            ));
+        }
+        if let Some(activity) = segment.activity {
+            parsed_import.push(get_timeline_location(
+                activity.start.and_then(|place| place.lat_lng).as_deref(),
+                segment.start_time.as_deref(),
+            ));
+            parsed_import.push(get_timeline_location(
+                activity.end.and_then(|place| place.lat_lng).as_deref(),
+                segment.end_time.as_deref(),
+            ));
+        }
+    }
+
+    for signal in takeout_file.raw_signals.unwrap_or_default() {
+        if let Some(position) = signal.position {
+            parsed_import.push(get_raw_position_location(position));
+        }
+    }
+
+    Ok(parsed_import)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_are_parsed_with_their_wifi_access_point() {
+        let parsed_import = parse_takeout_file(
+            br#"{"locations": [
+                {"latitudeE7": 512353000, "longitudeE7": 67829000, "accuracy": 12, "altitude": 38, "velocity": 10, "heading": 90, "timestamp": "2023-11-14T22:13:20.123Z",
+                 "activeWifiScan": {"accessPoints": [{"mac": "244837814042624", "strength": -60}, {"mac": 1, "strength": -40, "isConnected": true}]}},
+                {"latitudeE7": 3394967296, "longitudeE7": 67829000, "timestampMs": "1700000000000"},
+                {"latitudeE7": 512353000, "timestamp": "2023-11-14T22:13:20Z"},
+                {"latitudeE7": 512353000, "longitudeE7": 67829000, "timestamp": "yesterday"}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(parsed_import.locations.len(), 2);
+        assert_eq!(parsed_import.rejected_locations, 2);
+
+        let location = &parsed_import.locations[0];
+        assert_eq!((location.latitude, location.longitude), (51.2353, 6.7829));
+        assert_eq!(
+            location.measurement_time.and_utc().timestamp_millis(),
+            1_700_000_000_123
+        );
+        assert_eq!(
+            (location.velocity, location.course_over_ground),
+            (Some(36), Some(90))
+        );
+        assert_eq!(location.wifi_bssid.as_deref(), Some("00:00:00:00:00:01"));
+
+        // the overflowed latitude has to be corrected
+        assert_eq!(parsed_import.locations[1].latitude, -90.0);
+    }
+
+    #[test]
+    fn test_timeline_segments_and_raw_positions_are_parsed() {
+        let parsed_import = parse_takeout_file(
+            r#"{"semanticSegments": [
+                    {"startTime": "2024-03-01T08:00:00.000+01:00", "endTime": "2024-03-01T10:00:00.000+01:00",
+                     "timelinePath": [{"point": "51.2353°, 6.7829°", "time": "2024-03-01T08:00:00.000+01:00"}, {"point": "51.24°, 6.79°", "time": "2024-03-01T08:02:00.000+01:00"}]},
+                    {"startTime": "2024-03-01T10:00:00.000+01:00", "endTime": "2024-03-01T12:00:00.000+01:00",
+                     "visit": {"topCandidate": {"placeLocation": {"latLng": "51.25°, 6.8°"}}}},
+                    {"startTime": "2024-03-01T12:00:00.000+01:00", "endTime": "2024-03-01T12:30:00.000+01:00",
+                     "activity": {"start": {"latLng": "51.25°, 6.8°"}, "end": {"latLng": "somewhere"}}}
+                ],
+                "rawSignals": [
+                    {"position": {"LatLng": "51.2353°, 6.7829°", "accuracyMeters": 13, "altitudeMeters": 41.6, "speedMetersPerSecond": 1.5, "timestamp": "2024-03-01T08:00:00.000Z"}},
+                    {"wifiScan": {"devicesRecords": []}}
+                ]}"#
+            .as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(parsed_import.locations.len(), 5);
+        assert_eq!(parsed_import.rejected_locations, 1);
+        assert_eq!(
+            parsed_import.locations[0]
+                .measurement_time
+                .and_utc()
+                .timestamp(),
+            1_709_276_400
+        );
+        assert_eq!(
+            (
+                parsed_import.locations[2].latitude,
+                parsed_import.locations[2].longitude
+            ),
+            (51.25, 6.8)
+        );
+
+        let raw_position = &parsed_import.locations[4];
+        assert_eq!(
+            (
+                raw_position.horizontal_accuracy,
+                raw_position.altitude,
+                raw_position.velocity
+            ),
+            (Some(13), Some(42), Some(5))
+        );
+
+        assert!(parse_takeout_file(br#"{"type": "FeatureCollection"}"#).is_err());
+    }
+}
@@ -14,14 +14,102 @@ use std::collections::HashSet;
 use std::fmt;
 
 pub mod fairings;
+pub mod geo;
+pub mod geojson;
 mod guards;
+pub mod imports;
 pub mod models;
 pub mod routes;
 pub mod schema;
+#[cfg(test)]
+mod test_fixtures;
 
 lazy_static! {
     /// The time in seconds a token is valid.
     static ref TOKEN_LIFETIME_IN_SECONDS: usize = 60 * 60;
+    /// The time in seconds a refresh token can be used to get a new access token.
+    static ref REFRESH_TOKEN_LIFETIME_IN_SECONDS: i64 = 30 * 24 * 60 * 60;
+}
+
+/// The number of characters of a newly generated refresh token.
+const REFRESH_TOKEN_LENGTH: usize = 64;
+
+/// The number of characters of the unique identifier (`jti`) of an access token.
+const TOKEN_ID_LENGTH: usize = 32;
+
+/// The number of characters of a newly generated client secret.
+pub const CLIENT_SECRET_LENGTH: usize = 32;
+
+/// The bcrypt cost which is used for hashing client secrets. The secrets are randomly generated
+/// and every OwnTracks request has to be verified, so we use a lower cost than for user passwords.
+pub const CLIENT_SECRET_HASH_COST: u32 = 10;
+
+/// The bcrypt cost which is used for hashing user passwords.
+pub const PASSWORD_HASH_COST: u32 = 12;
+
+/// The minimal number of characters a user password has to have.
+pub const MINIMUM_PASSWORD_LENGTH: usize = 8;
+
+/// Generate a random alphanumeric string with the supplied length.
+fn generate_random_string(length: usize) -> String {
+    use rand::distributions::Alphanumeric;
+    use rand::Rng;
+
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+/// Generate a new random secret for a client token.
+pub fn generate_client_secret() -> String {
+    generate_random_string(CLIENT_SECRET_LENGTH)
+}
+
+/// Generate a new random identifier for a client token.
+pub fn generate_client_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Generate a new random refresh token.
+pub fn generate_refresh_token() -> String {
+    generate_random_string(REFRESH_TOKEN_LENGTH)
+}
+
+/// Hash the supplied refresh token so it can be stored in and looked up from the database. The
+/// tokens are long random strings, so a fast hash is sufficient here.
+pub fn hash_refresh_token(refresh_token: &str) -> String {
+    use sha2::{Digest, Sha256};
+
+    format!("{:x}", Sha256::digest(refresh_token.as_bytes()))
+}
+
+/// Get the time until a newly issued refresh token can be used.
+pub fn get_refresh_token_expiry_time() -> chrono::NaiveDateTime {
+    Utc::now().naive_utc() + chrono::Duration::seconds(*REFRESH_TOKEN_LIFETIME_IN_SECONDS)
+}
+
+/// Hash the supplied client secret so it can be stored in the database.
+pub fn hash_client_secret(client_secret: &str) -> Option<String> {
+    match bcrypt::hash(client_secret, CLIENT_SECRET_HASH_COST) {
+        Ok(hashed_secret) => Some(hashed_secret),
+        Err(error) => {
+            error!("Could not hash the client secret. The error was: {}", error);
+            None
+        }
+    }
+}
+
+/// Hash the supplied user password so it can be stored in the database.
+pub fn hash_password(password: &str) -> Option<String> {
+    match bcrypt::hash(password, PASSWORD_HASH_COST) {
+        Ok(hashed_password) => Some(hashed_password),
+        Err(error) => {
+            error!("Could not hash the user password. The error was: {}", error);
+            None
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -83,6 +171,17 @@ pub fn custom_handler_internal_server_error(_: &Request<'_>) -> Json<CustomHandl
 pub enum AuditLogAction {
     ClientTokenAuthentication,
     UserAuthentication,
+    TokenRefresh,
+    UserLogout,
+    UserCreation,
+    UserDeletion,
+    RoleAssignment,
+    PasswordChange,
+    DeviceRegistration,
+    DeviceUpdate,
+    DeviceSecretRotation,
+    DeviceRevocation,
+    LocationImport,
 }
 
 impl fmt::Display for AuditLogAction {
@@ -90,6 +189,17 @@ impl fmt::Display for AuditLogAction {
         match self {
             AuditLogAction::ClientTokenAuthentication => write!(f, "client_token_authentication"),
             AuditLogAction::UserAuthentication => write!(f, "user_authentication"),
+            AuditLogAction::TokenRefresh => write!(f, "token_refresh"),
+            AuditLogAction::UserLogout => write!(f, "user_logout"),
+            AuditLogAction::UserCreation => write!(f, "user_creation"),
+            AuditLogAction::UserDeletion => write!(f, "user_deletion"),
+            AuditLogAction::RoleAssignment => write!(f, "role_assignment"),
+            AuditLogAction::PasswordChange => write!(f, "password_change"),
+            AuditLogAction::DeviceRegistration => write!(f, "device_registration"),
+            AuditLogAction::DeviceUpdate => write!(f, "device_update"),
+            AuditLogAction::DeviceSecretRotation => write!(f, "device_secret_rotation"),
+            AuditLogAction::DeviceRevocation => write!(f, "device_revocation"),
+            AuditLogAction::LocationImport => write!(f, "location_import"),
         }
     }
 }
@@ -142,6 +252,7 @@ pub fn log_audit_message(
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Claims {
+    jti: String,
     exp: usize,
     iat: usize,
     nbf: usize,
@@ -177,6 +288,7 @@ pub fn get_token_for_user(
 
     // define the content of the actual token
     let token_claims = Claims {
+        jti: generate_random_string(TOKEN_ID_LENGTH),
         exp: token_expires_at,
         iat: token_issued_at,
         nbf: token_issued_at + 1,
@@ -208,3 +320,31 @@ pub struct BackendConfiguration {
     /// A list of URLs which represent the audience for this token.
     pub token_audience: HashSet<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_client_secrets_can_be_verified_after_hashing() {
+        let client_secret = generate_client_secret();
+        assert_eq!(client_secret.len(), CLIENT_SECRET_LENGTH);
+        assert!(client_secret.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(client_secret, generate_client_secret());
+
+        let hashed_secret = hash_client_secret(&client_secret).unwrap();
+        assert!(bcrypt::verify(&client_secret, &hashed_secret).unwrap());
+        assert!(!bcrypt::verify("somesecret", &hashed_secret).unwrap());
+    }
+
+    #[test]
+    fn test_refresh_tokens_are_hashed_as_hex_encoded_sha256() {
+        let refresh_token = generate_refresh_token();
+        assert_eq!(refresh_token.len(), REFRESH_TOKEN_LENGTH);
+        assert_eq!(hash_refresh_token(&refresh_token).len(), 64);
+        assert_eq!(
+            hash_refresh_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -1,8 +1,8 @@
 use chrono::Utc;
-use diesel::PgConnection;
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl, TextExpressionMethods};
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use jsonwebtoken::{DecodingKey, EncodingKey};
-use log::{debug, error, info, LevelFilter};
+use log::{debug, error, info, warn, LevelFilter};
 use rocket::config::{Shutdown, Sig};
 use rocket::figment::{
     util::map,
@@ -15,15 +15,47 @@ use std::io::Read;
 use std::path::Path;
 use std::time::Duration;
 use thereiwas::fairings::{ThereIWasDatabaseConnection, CORS};
+use thereiwas::models::ClientToken;
+use thereiwas::routes::beacons::{get_beacon_sightings, get_beacon_sightings_options};
+use thereiwas::routes::commands::{
+    add_device_command, get_device_commands, get_device_commands_options,
+};
+use thereiwas::routes::device_status::{get_device_status, get_device_status_options};
+use thereiwas::routes::devices::{
+    add_device, get_device_options, get_device_secret_options, get_devices, get_devices_options,
+    revoke_device, rotate_device_secret, update_device,
+};
+use thereiwas::routes::exports::{
+    get_csv_export, get_csv_export_options, get_gpx_export, get_gpx_export_options, get_kml_export,
+    get_kml_export_options, get_kmz_export, get_kmz_export_options,
+};
+use thereiwas::routes::friends::{
+    add_device_share, delete_device_share, get_device_card, get_device_card_options,
+    get_device_share_options, get_device_shares, get_device_shares_options, set_device_card,
+};
+use thereiwas::routes::imports::{
+    import_takeout, import_takeout_options, import_track, import_track_options,
+};
+use thereiwas::routes::last_wills::{get_last_wills, get_last_wills_options};
 use thereiwas::routes::owntracks::add_new_location_record;
+use thereiwas::routes::regions::{
+    add_region, delete_region, get_region_options, get_regions, get_regions_options, update_region,
+};
+use thereiwas::routes::steps::{get_step_counts, get_step_counts_options};
+use thereiwas::routes::transitions::{get_transitions, get_transitions_options};
+use thereiwas::routes::users::{
+    add_user, change_password, delete_user, get_password_options, get_user_options,
+    get_user_roles_options, get_users, get_users_options, set_user_roles,
+};
 use thereiwas::routes::{
     get_health_status, get_login_token, get_login_token_options, get_positions,
-    get_positions_options,
+    get_positions_options, logout, logout_options, refresh_login_token,
+    refresh_login_token_options,
 };
 use thereiwas::{
     custom_handler_bad_request, custom_handler_conflict, custom_handler_forbidden,
     custom_handler_internal_server_error, custom_handler_not_found, custom_handler_unauthorized,
-    custom_handler_unprocessable_entity, BackendConfiguration,
+    custom_handler_unprocessable_entity, hash_client_secret, BackendConfiguration,
 };
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/");
@@ -49,6 +81,49 @@ pub fn run_migrations(connection: &mut PgConnection) {
     }
 }
 
+/// Replace all client secrets which are still stored in plaintext (e.g. from before the secrets
+/// were hashed) by their bcrypt hash.
+pub fn hash_plaintext_client_secrets(connection: &mut PgConnection) {
+    use thereiwas::schema::client_tokens::dsl::client_tokens;
+    use thereiwas::schema::client_tokens::secret_hash;
+
+    let plaintext_client_tokens = match client_tokens
+        .filter(secret_hash.not_like("$2%"))
+        .load::<ClientToken>(connection)
+    {
+        Ok(plaintext_client_tokens) => plaintext_client_tokens,
+        Err(error) => {
+            error!(
+                "Could not query the client tokens with plaintext secrets. The error was: {}",
+                error
+            );
+            return;
+        }
+    };
+
+    for client_token in &plaintext_client_tokens {
+        let Some(hashed_secret) = hash_client_secret(&client_token.secret_hash) else {
+            continue;
+        };
+        if let Err(error) = diesel::update(client_tokens.find(client_token.id))
+            .set(secret_hash.eq(hashed_secret))
+            .execute(connection)
+        {
+            error!(
+                "Could not store the hashed secret of the client token {}. The error was: {}",
+                client_token.id, error
+            );
+        }
+    }
+
+    if !plaintext_client_tokens.is_empty() {
+        warn!(
+            "Hashed the plaintext secrets of {} client tokens. Consider rotating them since the secrets were stored in plaintext before",
+            plaintext_client_tokens.len()
+        );
+    }
+}
+
 async fn setup_logging(logging_level: LevelFilter, logfile_path: &String) {
     let mut base_config = fern::Dispatch::new();
     let parsed_logfile_path = Path::new(logfile_path);
@@ -166,6 +241,20 @@ async fn main() {
     let private_key_file_path = std::env::var("THEREIWAS_JWT_PRIVATE_KEY_FILE")
         .unwrap_or_else(|_| "/usr/local/thereiwas/private.key".to_string());
 
+    let api_host =
+        std::env::var("THEREIWAS_API_HOST").unwrap_or_else(|_| "http://localhost:3000".to_string());
+    let token_audience = std::env::var("THEREIWAS_TOKEN_AUDIENCE")
+        .map(|audience| {
+            audience
+                .split(',')
+                .map(|entry| entry.trim().to_string())
+                .filter(|entry| !entry.is_empty())
+                .collect::<HashSet<_>>()
+        })
+        .ok()
+        .filter(|audience| !audience.is_empty())
+        .unwrap_or_else(|| HashSet::from([api_host.clone()]));
+
     let database_connection_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let db_connection_pool_manager = diesel::r2d2::ConnectionManager::new(&database_connection_url);
     let db_connection_pool = diesel::r2d2::Pool::builder()
@@ -183,6 +272,7 @@ async fn main() {
         std::process::exit(-1);
     });
     run_migrations(&mut db_connection);
+    hash_plaintext_client_secrets(&mut db_connection);
     info!("Database preparations finished");
 
     let thereiwas_database_config: Map<_, Value> = map! { // TODO: there are two different ways for accessing the db right now
@@ -191,10 +281,10 @@ async fn main() {
     };
 
     let backend_config = BackendConfiguration {
-        api_host: "some_host".to_string(), // TODO: this
+        api_host,
         encoding_key: Some(get_encoding_key(private_key_file_path.as_str())),
         decoding_key: Some(get_decoding_key(public_key_file_path.as_str())),
-        token_audience: HashSet::new(), // TODO: this
+        token_audience,
     };
 
     let rocket_configuration_figment = RocketConfig::figment()
@@ -228,9 +318,69 @@ async fn main() {
                 get_positions_options,
                 get_login_token_options,
                 get_login_token,
+                refresh_login_token_options,
+                refresh_login_token,
+                logout_options,
+                logout,
                 get_health_status,
                 add_new_location_record,
-                get_positions
+                get_positions,
+                get_transitions_options,
+                get_transitions,
+                get_regions_options,
+                get_region_options,
+                get_regions,
+                add_region,
+                update_region,
+                delete_region,
+                get_device_commands_options,
+                get_device_commands,
+                add_device_command,
+                get_device_card_options,
+                get_device_shares_options,
+                get_device_share_options,
+                get_device_card,
+                set_device_card,
+                get_device_shares,
+                add_device_share,
+                delete_device_share,
+                get_device_status_options,
+                get_device_status,
+                get_devices_options,
+                get_device_options,
+                get_device_secret_options,
+                get_devices,
+                add_device,
+                update_device,
+                rotate_device_secret,
+                revoke_device,
+                get_gpx_export_options,
+                get_gpx_export,
+                get_kml_export_options,
+                get_kml_export,
+                get_kmz_export_options,
+                get_kmz_export,
+                get_csv_export_options,
+                get_csv_export,
+                import_takeout_options,
+                import_takeout,
+                import_track_options,
+                import_track,
+                get_beacon_sightings_options,
+                get_beacon_sightings,
+                get_step_counts_options,
+                get_step_counts,
+                get_last_wills_options,
+                get_last_wills,
+                get_users_options,
+                get_user_options,
+                get_user_roles_options,
+                get_password_options,
+                get_users,
+                add_user,
+                set_user_roles,
+                change_password,
+                delete_user
             ],
         )
         .register(
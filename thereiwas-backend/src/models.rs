@@ -1,8 +1,11 @@
 use crate::schema::{
-    audit_log, client_tokens, locations, locations_to_wifi_access_points, users, wifi_access_points,
+    audit_log, beacon_sightings, client_tokens, device_cards, device_commands, device_status,
+    last_wills, location_shares, locations, locations_to_wifi_access_points, refresh_tokens,
+    regions, revoked_access_tokens, step_counts, transitions, users, users_to_roles,
+    wifi_access_points,
 };
 use chrono::NaiveDateTime;
-use diesel::{Insertable, Queryable, Selectable};
+use diesel::{AsChangeset, Insertable, Queryable, Selectable};
 
 #[derive(Queryable, Selectable, Clone)]
 #[diesel(table_name = locations)]
@@ -18,6 +21,16 @@ pub struct Location {
     pub barometric_pressure: Option<f64>,
     pub created_at: Option<NaiveDateTime>,
     pub reporting_device: i32,
+    pub battery_level: Option<i16>,
+    pub battery_status: Option<i16>,
+    pub monitoring_mode: Option<i16>,
+    pub velocity: Option<i32>,
+    pub course_over_ground: Option<i32>,
+    pub connection_type: Option<String>,
+    pub topic: Option<String>,
+    pub in_regions: Option<Vec<Option<String>>>,
+    pub in_region_ids: Option<Vec<Option<String>>>,
+    pub grid_cell: i32,
 }
 
 #[derive(Insertable)]
@@ -33,6 +46,151 @@ pub struct NewLocation {
     pub barometric_pressure: Option<f64>,
     pub created_at: Option<NaiveDateTime>,
     pub reporting_device: i32,
+    pub battery_level: Option<i16>,
+    pub battery_status: Option<i16>,
+    pub monitoring_mode: Option<i16>,
+    pub velocity: Option<i32>,
+    pub course_over_ground: Option<i32>,
+    pub connection_type: Option<String>,
+    pub topic: Option<String>,
+    pub in_regions: Option<Vec<Option<String>>>,
+    pub in_region_ids: Option<Vec<Option<String>>>,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = transitions)]
+pub struct Transition {
+    pub id: i32,
+    pub event: String,
+    pub region_id: Option<String>,
+    pub region_description: Option<String>,
+    pub region_created_at: NaiveDateTime,
+    pub report_trigger: String,
+    pub horizontal_accuracy: Option<i32>,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub measurement_time: NaiveDateTime,
+    pub reporting_device: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = transitions)]
+pub struct NewTransition {
+    pub event: String,
+    pub region_id: Option<String>,
+    pub region_description: Option<String>,
+    pub region_created_at: NaiveDateTime,
+    pub report_trigger: String,
+    pub horizontal_accuracy: Option<i32>,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub measurement_time: NaiveDateTime,
+    pub reporting_device: i32,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = beacon_sightings)]
+pub struct BeaconSighting {
+    pub id: i32,
+    pub description: Option<String>,
+    pub beacon_uuid: String,
+    pub beacon_major: i32,
+    pub beacon_minor: i32,
+    pub proximity: i32,
+    pub rssi: i32,
+    pub horizontal_accuracy: Option<i32>,
+    pub measurement_time: NaiveDateTime,
+    pub reporting_device: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = beacon_sightings)]
+pub struct NewBeaconSighting {
+    pub description: Option<String>,
+    pub beacon_uuid: String,
+    pub beacon_major: i32,
+    pub beacon_minor: i32,
+    pub proximity: i32,
+    pub rssi: i32,
+    pub horizontal_accuracy: Option<i32>,
+    pub measurement_time: NaiveDateTime,
+    pub reporting_device: i32,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = step_counts)]
+pub struct StepCount {
+    pub id: i32,
+    pub steps: i32,
+    pub distance: Option<i32>,
+    pub floors_up: Option<i32>,
+    pub floors_down: Option<i32>,
+    pub period_start: NaiveDateTime,
+    pub period_end: NaiveDateTime,
+    pub measurement_time: NaiveDateTime,
+    pub reporting_device: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = step_counts)]
+pub struct NewStepCount {
+    pub steps: i32,
+    pub distance: Option<i32>,
+    pub floors_up: Option<i32>,
+    pub floors_down: Option<i32>,
+    pub period_start: NaiveDateTime,
+    pub period_end: NaiveDateTime,
+    pub measurement_time: NaiveDateTime,
+    pub reporting_device: i32,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = last_wills)]
+pub struct LastWill {
+    pub id: i32,
+    pub connected_at: NaiveDateTime,
+    pub received_at: NaiveDateTime,
+    pub reporting_device: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = last_wills)]
+pub struct NewLastWill {
+    pub connected_at: NaiveDateTime,
+    pub received_at: NaiveDateTime,
+    pub reporting_device: i32,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = regions)]
+pub struct Region {
+    pub id: i32,
+    pub reporting_device: i32,
+    pub region_id: Option<String>,
+    pub description: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius: i32,
+    pub beacon_uuid: Option<String>,
+    pub beacon_major: Option<i32>,
+    pub beacon_minor: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = regions)]
+#[diesel(treat_none_as_null = true)]
+pub struct NewRegion {
+    pub reporting_device: i32,
+    pub region_id: Option<String>,
+    pub description: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius: i32,
+    pub beacon_uuid: Option<String>,
+    pub beacon_major: Option<i32>,
+    pub beacon_minor: Option<i32>,
+    pub created_at: NaiveDateTime,
 }
 
 #[derive(Queryable, Selectable)]
@@ -64,9 +222,144 @@ pub struct NewLocationToWifiAccessPoint {
 pub struct ClientToken {
     pub id: i32,
     pub client: String,
-    pub secret: String,
+    pub secret_hash: String,
     pub description: Option<String>,
     pub health_callback_url: Option<String>,
+    pub encryption_key: Option<String>,
+    pub reported_user: Option<String>,
+    pub reported_device: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub user_id: Option<i32>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = client_tokens)]
+pub struct NewClientToken {
+    pub client: String,
+    pub secret_hash: String,
+    pub description: Option<String>,
+    pub health_callback_url: Option<String>,
+    pub user_id: Option<i32>,
+}
+
+/// The changes of a device, fields which are `None` are not changed.
+#[derive(AsChangeset)]
+#[diesel(table_name = client_tokens)]
+pub struct DeviceChange {
+    pub description: Option<Option<String>>,
+    pub health_callback_url: Option<Option<String>>,
+}
+
+/// The user and device name an OwnTracks app reported, fields which are `None` are not changed.
+#[derive(AsChangeset)]
+#[diesel(table_name = client_tokens)]
+pub struct ReportedIdentityChange<'a> {
+    pub reported_user: Option<&'a str>,
+    pub reported_device: Option<&'a str>,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = device_commands)]
+pub struct DeviceCommand {
+    pub id: i32,
+    pub reporting_device: i32,
+    pub payload: serde_json::Value,
+    pub created_at: NaiveDateTime,
+    pub delivered_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = device_commands)]
+pub struct NewDeviceCommand {
+    pub reporting_device: i32,
+    pub payload: serde_json::Value,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = device_cards)]
+pub struct DeviceCard {
+    pub id: i32,
+    pub reporting_device: i32,
+    pub tracker_id: Option<String>,
+    pub name: String,
+    pub face: Option<String>,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = device_cards)]
+#[diesel(treat_none_as_null = true)]
+pub struct NewDeviceCard {
+    pub reporting_device: i32,
+    pub tracker_id: Option<String>,
+    pub name: String,
+    pub face: Option<String>,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = location_shares)]
+pub struct LocationShare {
+    pub id: i32,
+    pub sharing_device: i32,
+    pub viewing_device: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = location_shares)]
+pub struct NewLocationShare {
+    pub sharing_device: i32,
+    pub viewing_device: i32,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = device_status)]
+pub struct DeviceStatus {
+    pub id: i32,
+    pub reporting_device: i32,
+    pub request_id: Option<String>,
+    pub received_at: NaiveDateTime,
+    pub altimeter_authorization_status: Option<String>,
+    pub altimeter_is_relative_altitude_available: Option<bool>,
+    pub background_refresh_status: Option<String>,
+    pub device_identifier_for_vendor: Option<String>,
+    pub device_model: Option<String>,
+    pub device_system_name: Option<String>,
+    pub device_system_version: Option<String>,
+    pub device_user_interface_idiom: Option<String>,
+    pub locale: Option<String>,
+    pub locale_uses_metric_system: Option<bool>,
+    pub location_manager_authorization_status: Option<String>,
+    pub app_version: Option<String>,
+    pub app_hibernation: Option<i32>,
+    pub battery_optimizations: Option<i32>,
+    pub location_permission: Option<i32>,
+    pub power_save: Option<i32>,
+    pub wifi_state: Option<i32>,
+}
+
+#[derive(Insertable, Default)]
+#[diesel(table_name = device_status)]
+pub struct NewDeviceStatus {
+    pub reporting_device: i32,
+    pub request_id: Option<String>,
+    pub received_at: NaiveDateTime,
+    pub altimeter_authorization_status: Option<String>,
+    pub altimeter_is_relative_altitude_available: Option<bool>,
+    pub background_refresh_status: Option<String>,
+    pub device_identifier_for_vendor: Option<String>,
+    pub device_model: Option<String>,
+    pub device_system_name: Option<String>,
+    pub device_system_version: Option<String>,
+    pub device_user_interface_idiom: Option<String>,
+    pub locale: Option<String>,
+    pub locale_uses_metric_system: Option<bool>,
+    pub location_manager_authorization_status: Option<String>,
+    pub app_version: Option<String>,
+    pub app_hibernation: Option<i32>,
+    pub battery_optimizations: Option<i32>,
+    pub location_permission: Option<i32>,
+    pub power_save: Option<i32>,
+    pub wifi_state: Option<i32>,
 }
 
 #[derive(Insertable)]
@@ -85,3 +378,44 @@ pub struct User {
     pub username: String,
     pub password_hash: String,
 }
+
+#[derive(Insertable)]
+#[diesel(table_name = users)]
+pub struct NewUser {
+    pub username: String,
+    pub password_hash: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = users_to_roles)]
+pub struct NewUserToRole {
+    pub user_id: i32,
+    pub role_id: i32,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = refresh_tokens)]
+pub struct RefreshToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub token_hash: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = refresh_tokens)]
+pub struct NewRefreshToken {
+    pub user_id: i32,
+    pub token_hash: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = revoked_access_tokens)]
+pub struct NewRevokedAccessToken {
+    pub token_id: String,
+    pub expires_at: NaiveDateTime,
+}
@@ -1,24 +1,46 @@
 use crate::fairings::ThereIWasDatabaseConnection;
-use crate::guards::AuthenticatedClient;
-use crate::models::{Location, User};
+use crate::geo::{haversine_distance, simplify_track, BoundingBox};
+use crate::geojson::{get_line_features, get_point_features, FeatureCollection};
+use crate::guards::AuthenticatedUser;
+use crate::models::{Location, NewRefreshToken, NewRevokedAccessToken, RefreshToken, User};
 use crate::schema::locations::dsl::locations;
 use crate::schema::locations::{measurement_time, reporting_device};
-use crate::schema::users::dsl::users;
+use crate::schema::users::dsl::users as users_table;
 use crate::schema::users::username;
 use crate::{
-    get_token_for_user, log_audit_message, AuditLogAction, AuditLogResult, BackendConfiguration,
+    generate_refresh_token, get_refresh_token_expiry_time, get_token_for_user, hash_refresh_token,
+    log_audit_message, AuditLogAction, AuditLogResult, BackendConfiguration,
 };
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
 use bcrypt::verify;
-use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
-use log::error;
-use rocket::http::Status;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension, PgConnection,
+    QueryDsl, RunQueryDsl,
+};
+use log::{error, warn};
+use rocket::http::{ContentType, Status};
 use rocket::serde::json::Json;
-use rocket::{get, options, post, State};
+use rocket::{get, options, post, FromForm, FromFormField, Responder, State};
 use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
 use std::net::IpAddr;
 
+pub mod beacons;
+pub mod commands;
+pub mod device_status;
+pub mod devices;
+pub mod exports;
+pub mod friends;
 pub mod guards;
+pub mod imports;
+pub mod last_wills;
 pub mod owntracks;
+pub mod regions;
+pub mod steps;
+pub mod transitions;
+pub mod users;
 
 #[get("/health")]
 pub fn get_health_status(_db_connection_pool: &State<ThereIWasDatabaseConnection>) -> Status {
@@ -33,11 +55,37 @@ pub struct LoginInformation {
     password: String,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshInformation {
+    /// The refresh token which was issued together with the last access token.
+    refresh_token: String,
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TokenResponse {
     /// The access token to use for API requests.
     access_token: String,
+    /// The token which can be used once to get a new access token.
+    refresh_token: String,
+}
+
+/// Store a new refresh token for the supplied user and return it.
+fn issue_refresh_token(
+    user_id: i32,
+    db_connection: &mut PgConnection,
+) -> Result<String, diesel::result::Error> {
+    let refresh_token = generate_refresh_token();
+    diesel::insert_into(crate::schema::refresh_tokens::table)
+        .values(&NewRefreshToken {
+            user_id,
+            token_hash: hash_refresh_token(&refresh_token),
+            created_at: Utc::now().naive_utc(),
+            expires_at: get_refresh_token_expiry_time(),
+        })
+        .execute(db_connection)?;
+    Ok(refresh_token)
 }
 
 #[derive(Serialize)]
@@ -48,6 +96,112 @@ pub struct LocationRecord {
     pub vertical_accuracy: Option<i32>,
     pub altitude: Option<i32>,
     pub measurement_time: i32,
+    pub battery_level: Option<i16>,
+    pub battery_status: Option<i16>,
+    pub monitoring_mode: Option<i16>,
+    pub velocity: Option<i32>,
+    pub course_over_ground: Option<i32>,
+    pub connection_type: Option<String>,
+    pub topic: Option<String>,
+    pub reporting_device: i32,
+    pub in_regions: Vec<String>,
+    pub in_region_ids: Vec<String>,
+}
+
+impl From<Location> for LocationRecord {
+    fn from(loc: Location) -> Self {
+        LocationRecord {
+            longitude: loc.longitude,
+            latitude: loc.latitude,
+            horizontal_accuracy: loc.horizontal_accuracy,
+            vertical_accuracy: loc.vertical_accuracy,
+            altitude: loc.altitude,
+            measurement_time: loc.measurement_time.and_utc().timestamp() as i32,
+            battery_level: loc.battery_level,
+            battery_status: loc.battery_status,
+            monitoring_mode: loc.monitoring_mode,
+            velocity: loc.velocity,
+            course_over_ground: loc.course_over_ground,
+            connection_type: loc.connection_type,
+            topic: loc.topic,
+            reporting_device: loc.reporting_device,
+            in_regions: loc
+                .in_regions
+                .unwrap_or_default()
+                .into_iter()
+                .flatten()
+                .collect(),
+            in_region_ids: loc
+                .in_region_ids
+                .unwrap_or_default()
+                .into_iter()
+                .flatten()
+                .collect(),
+        }
+    }
+}
+
+/// The maximal number of positions which can be requested at once.
+const MAXIMUM_POSITIONS_LIMIT: i64 = 1000;
+
+/// The maximal number of raw positions which can be requested at once if they get simplified
+/// before they are returned.
+const MAXIMUM_SIMPLIFIED_POSITIONS_LIMIT: i64 = 100_000;
+
+/// The largest time bucket (one week) which can be used for reducing the positions.
+const MAXIMUM_BUCKET_MINUTES: i64 = 7 * 24 * 60;
+
+#[derive(Serialize)]
+pub struct PositionsResponse {
+    /// The requested positions, the newest position first.
+    pub positions: Vec<LocationRecord>,
+    /// The number of positions which are returned.
+    pub returned_positions: usize,
+    /// The number of positions which were found before they were simplified.
+    pub raw_positions: usize,
+    /// The cursor which can be used to get the next (older) page of positions, if there are any.
+    pub next_cursor: Option<String>,
+}
+
+/// The position after which the next page of positions starts. Positions are ordered by their
+/// measurement time and their id, so the cursor stays stable while new positions are reported.
+#[derive(Debug, PartialEq)]
+struct PositionCursor {
+    measurement_time: NaiveDateTime,
+    id: i32,
+}
+
+impl PositionCursor {
+    fn encode(&self) -> String {
+        BASE64_URL_SAFE_NO_PAD.encode(format!(
+            "{}:{}",
+            self.measurement_time.and_utc().timestamp_micros(),
+            self.id
+        ))
+    }
+
+    fn decode(cursor: &str) -> Option<PositionCursor> {
+        let decoded_cursor = String::from_utf8(BASE64_URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+        let (measurement_time_micros, id) = decoded_cursor.split_once(':')?;
+        Some(PositionCursor {
+            measurement_time: DateTime::from_timestamp_micros(
+                measurement_time_micros.parse().ok()?,
+            )?
+            .naive_utc(),
+            id: id.parse().ok()?,
+        })
+    }
+}
+
+/// Convert the supplied UNIX timestamp of a query parameter into a date.
+fn parse_timestamp(timestamp: Option<i64>) -> Result<Option<NaiveDateTime>, Status> {
+    timestamp
+        .map(|timestamp| {
+            DateTime::from_timestamp(timestamp, 0)
+                .map(|date| date.naive_utc())
+                .ok_or(Status::BadRequest)
+        })
+        .transpose()
 }
 
 #[options("/positions")]
@@ -55,43 +209,366 @@ pub fn get_positions_options() -> Status {
     Status::Ok
 }
 
-#[get("/positions")]
-pub fn get_positions(
+#[derive(FromForm)]
+pub struct PositionFilter {
+    /// Only return positions which were measured at or after this UNIX timestamp.
+    from: Option<i64>,
+    /// Only return positions which were measured at or before this UNIX timestamp.
+    to: Option<i64>,
+    /// Only return positions of these devices (all accessible devices if empty).
+    devices: Vec<i32>,
+    /// The maximal number of positions to return.
+    limit: Option<i64>,
+    /// The `next_cursor` of the previous page of positions.
+    cursor: Option<String>,
+    /// The southern border of the area the positions have to be in.
+    min_latitude: Option<f64>,
+    /// The western border of the area the positions have to be in.
+    min_longitude: Option<f64>,
+    /// The northern border of the area the positions have to be in.
+    max_latitude: Option<f64>,
+    /// The eastern border of the area the positions have to be in.
+    max_longitude: Option<f64>,
+    /// The latitude of the center of the circle the positions have to be in.
+    latitude: Option<f64>,
+    /// The longitude of the center of the circle the positions have to be in.
+    longitude: Option<f64>,
+    /// The radius in meters of the circle the positions have to be in.
+    radius: Option<f64>,
+    /// The tolerance in meters for simplifying the tracks of the devices.
+    simplify: Option<f64>,
+    /// Only return at most one position of each device per this number of minutes.
+    bucket_minutes: Option<i64>,
+    /// The format the positions are returned in.
+    format: Option<PositionsFormat>,
+    /// The geometry which is used for the positions if they are returned as GeoJSON.
+    #[field(name = "as")]
+    geometry: Option<GeoJsonGeometry>,
+}
+
+#[derive(FromFormField)]
+pub enum PositionsFormat {
+    /// A list of location records.
+    Json,
+    /// A GeoJSON FeatureCollection.
+    GeoJson,
+}
+
+#[derive(FromFormField)]
+pub enum GeoJsonGeometry {
+    /// Every position is a point feature.
+    Points,
+    /// The positions of each day and device are combined to a line feature.
+    Lines,
+}
+
+#[derive(Responder)]
+pub enum PositionsResponder {
+    Json(Json<PositionsResponse>),
+    GeoJson((ContentType, Json<FeatureCollection>)),
+}
+
+/// The area the returned positions have to be in.
+enum SpatialFilter {
+    BoundingBox(BoundingBox),
+    Radius {
+        latitude: f64,
+        longitude: f64,
+        radius: f64,
+    },
+}
+
+impl PositionFilter {
+    /// Get the area the positions should be in, if any. The area can either be a bounding box or
+    /// a circle, but not both at the same time.
+    fn get_spatial_filter(&self) -> Result<Option<SpatialFilter>, Status> {
+        match (
+            self.min_latitude,
+            self.min_longitude,
+            self.max_latitude,
+            self.max_longitude,
+            self.latitude,
+            self.longitude,
+            self.radius,
+        ) {
+            (None, None, None, None, None, None, None) => Ok(None),
+            (
+                Some(min_latitude),
+                Some(min_longitude),
+                Some(max_latitude),
+                Some(max_longitude),
+                None,
+                None,
+                None,
+            ) => {
+                let bounding_box = BoundingBox {
+                    min_latitude,
+                    min_longitude,
+                    max_latitude,
+                    max_longitude,
+                };
+                if !bounding_box.is_valid() {
+                    return Err(Status::BadRequest);
+                }
+                Ok(Some(SpatialFilter::BoundingBox(bounding_box)))
+            }
+            (None, None, None, None, Some(latitude), Some(longitude), Some(radius)) => {
+                if !(-90.0..=90.0).contains(&latitude)
+                    || !(-180.0..=180.0).contains(&longitude)
+                    || !radius.is_finite()
+                    || radius <= 0.0
+                {
+                    return Err(Status::BadRequest);
+                }
+                Ok(Some(SpatialFilter::Radius {
+                    latitude,
+                    longitude,
+                    radius,
+                }))
+            }
+            _ => Err(Status::BadRequest),
+        }
+    }
+}
+
+/// Reduce the number of positions, e.g. for rendering them on a map. The positions of every device
+/// are reduced on their own, so the tracks of different devices do not get mixed up.
+fn reduce_positions(
+    positions: Vec<Location>,
+    bucket_minutes: Option<i64>,
+    tolerance_in_meters: Option<f64>,
+) -> Vec<Location> {
+    let mut positions_of_devices = BTreeMap::<i32, Vec<Location>>::new();
+    for position in positions {
+        positions_of_devices
+            .entry(position.reporting_device)
+            .or_default()
+            .push(position);
+    }
+
+    let mut reduced_positions = Vec::new();
+    for (_, mut device_positions) in positions_of_devices {
+        if let Some(bucket_minutes) = bucket_minutes {
+            // the positions are ordered by time, so the newest position of each bucket is kept
+            let mut used_buckets = HashSet::new();
+            device_positions.retain(|position| {
+                used_buckets.insert(
+                    position
+                        .measurement_time
+                        .and_utc()
+                        .timestamp()
+                        .div_euclid(bucket_minutes * 60),
+                )
+            });
+        }
+        if let Some(tolerance_in_meters) = tolerance_in_meters {
+            let track = device_positions
+                .iter()
+                .map(|position| (position.latitude, position.longitude))
+                .collect::<Vec<_>>();
+            device_positions = device_positions
+                .into_iter()
+                .zip(simplify_track(&track, tolerance_in_meters))
+                .filter_map(|(position, keep_position)| keep_position.then_some(position))
+                .collect();
+        }
+        reduced_positions.extend(device_positions);
+    }
+
+    reduced_positions.sort_by_key(|position| Reverse((position.measurement_time, position.id)));
+    reduced_positions
+}
+
+#[get("/positions?<filter..>")]
+pub async fn get_positions(
     db_connection_pool: &State<ThereIWasDatabaseConnection>,
-    _authenticated_client: AuthenticatedClient,
-) -> Result<Json<Vec<LocationRecord>>, Status> {
-    let mut db_connection = db_connection_pool
-        .get()
-        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user: AuthenticatedUser,
+    filter: PositionFilter,
+) -> Result<PositionsResponder, Status> {
+    use crate::schema::locations::{grid_cell, id as location_id, latitude, longitude};
 
-    let location_records = db_connection
-        .build_transaction()
-        .read_only()
-        .run::<_, diesel::result::Error, _>(|connection| {
-            locations
-                .filter(reporting_device.eq(1)) // TODO: change this to a parameter
-                .order_by(measurement_time.desc())
-                .limit(100) // TODO: change this to a parameter
-                .load::<Location>(connection)
-        })
-        .map_err(|e| match e {
-            diesel::result::Error::NotFound => Status::NotFound,
-            _ => Status::InternalServerError,
-        })?;
+    let from = parse_timestamp(filter.from)?;
+    let to = parse_timestamp(filter.to)?;
+    let cursor = filter
+        .cursor
+        .as_deref()
+        .map(|cursor| PositionCursor::decode(cursor).ok_or(Status::BadRequest))
+        .transpose()?;
+    if filter
+        .simplify
+        .is_some_and(|tolerance| !tolerance.is_finite() || tolerance <= 0.0)
+        || filter
+            .bucket_minutes
+            .is_some_and(|minutes| !(1..=MAXIMUM_BUCKET_MINUTES).contains(&minutes))
+    {
+        return Err(Status::BadRequest);
+    }
+    let is_simplified = filter.simplify.is_some() || filter.bucket_minutes.is_some();
+    let limit = filter.limit.unwrap_or(100).clamp(
+        1,
+        if is_simplified {
+            MAXIMUM_SIMPLIFIED_POSITIONS_LIMIT
+        } else {
+            MAXIMUM_POSITIONS_LIMIT
+        },
+    );
+    let spatial_filter = filter.get_spatial_filter()?;
+    let devices = filter.devices;
+    let (bucket_minutes, tolerance_in_meters) = (filter.bucket_minutes, filter.simplify);
 
-    let records = location_records
-        .into_iter()
-        .map(|loc| LocationRecord {
-            longitude: loc.longitude,
-            latitude: loc.latitude,
-            horizontal_accuracy: loc.horizontal_accuracy,
-            vertical_accuracy: loc.vertical_accuracy,
-            altitude: loc.altitude,
-            measurement_time: loc.measurement_time.and_utc().timestamp() as i32,
+    // loading and simplifying up to the maximal number of positions takes a while, so it should
+    // not block the executor
+    let db_connection_pool = db_connection_pool.inner().clone();
+    let (location_records, next_cursor, raw_positions) =
+        rocket::tokio::task::spawn_blocking(move || {
+            let mut db_connection = db_connection_pool
+                .get()
+                .map_err(|_| Status::ServiceUnavailable)?;
+
+            for device_id in &devices {
+                authenticated_user.ensure_device_access(*device_id, &mut db_connection)?;
+            }
+
+            let mut location_records = db_connection
+                .build_transaction()
+                .read_only()
+                .run::<_, diesel::result::Error, _>(|connection| {
+                    // one more position than requested is fetched to know if there is another page
+                    let mut location_query = locations
+                        .order_by((measurement_time.desc(), location_id.desc()))
+                        .limit(limit + 1)
+                        .into_boxed();
+
+                    // a user should only see the locations of their own devices
+                    if !devices.is_empty() {
+                        location_query = location_query.filter(reporting_device.eq_any(&devices));
+                    } else if let Some(accessible_devices) =
+                        authenticated_user.get_accessible_devices(connection)?
+                    {
+                        location_query =
+                            location_query.filter(reporting_device.eq_any(accessible_devices));
+                    }
+                    if let Some(from) = from {
+                        location_query = location_query.filter(measurement_time.ge(from));
+                    }
+                    if let Some(to) = to {
+                        location_query = location_query.filter(measurement_time.le(to));
+                    }
+                    match &spatial_filter {
+                        Some(SpatialFilter::BoundingBox(bounding_box)) => {
+                            location_query =
+                                location_query
+                                    .filter(latitude.between(
+                                        bounding_box.min_latitude,
+                                        bounding_box.max_latitude,
+                                    ))
+                                    .filter(longitude.between(
+                                        bounding_box.min_longitude,
+                                        bounding_box.max_longitude,
+                                    ));
+                            if let Some(grid_cells) = bounding_box.get_grid_cells() {
+                                location_query =
+                                    location_query.filter(grid_cell.eq_any(grid_cells));
+                            }
+                        }
+                        Some(SpatialFilter::Radius {
+                            latitude: center_latitude,
+                            longitude: center_longitude,
+                            radius,
+                        }) => {
+                            // the grid cells of the bounding box narrow down the locations before
+                            // the actual distance is calculated
+                            if let Some(grid_cells) =
+                                BoundingBox::around(*center_latitude, *center_longitude, *radius)
+                                    .get_grid_cells()
+                            {
+                                location_query =
+                                    location_query.filter(grid_cell.eq_any(grid_cells));
+                            }
+                            location_query = location_query.filter(
+                                haversine_distance(
+                                    *center_latitude,
+                                    *center_longitude,
+                                    latitude,
+                                    longitude,
+                                )
+                                .le(*radius),
+                            );
+                        }
+                        None => {}
+                    }
+                    if let Some(cursor) = &cursor {
+                        location_query = location_query.filter(
+                            measurement_time
+                                .lt(cursor.measurement_time)
+                                .or(measurement_time
+                                    .eq(cursor.measurement_time)
+                                    .and(location_id.lt(cursor.id))),
+                        );
+                    }
+
+                    location_query.load::<Location>(connection)
+                })
+                .map_err(|error| {
+                    error!(
+                        "Could not query the positions for the user '{}'. The error was: {}",
+                        authenticated_user.username, error
+                    );
+                    Status::InternalServerError
+                })?;
+
+            let next_cursor = if location_records.len() as i64 > limit {
+                location_records.truncate(limit as usize);
+                location_records.last().map(|last_location| {
+                    PositionCursor {
+                        measurement_time: last_location.measurement_time,
+                        id: last_location.id,
+                    }
+                    .encode()
+                })
+            } else {
+                None
+            };
+
+            // the cursor refers to the raw positions, so paging is not affected by the
+            // simplification
+            let raw_positions = location_records.len();
+            if is_simplified {
+                location_records =
+                    reduce_positions(location_records, bucket_minutes, tolerance_in_meters);
+            }
+
+            Ok((location_records, next_cursor, raw_positions))
         })
-        .collect();
+        .await
+        .map_err(|_| Status::InternalServerError)??;
+
+    let returned_positions = location_records.len();
+    if let Some(PositionsFormat::GeoJson) = filter.format {
+        let features = match filter.geometry {
+            Some(GeoJsonGeometry::Lines) => get_line_features(location_records),
+            Some(GeoJsonGeometry::Points) | None => get_point_features(location_records),
+        };
+        return Ok(PositionsResponder::GeoJson((
+            ContentType::new("application", "geo+json"),
+            Json(FeatureCollection {
+                features,
+                next_cursor,
+                returned_positions,
+                raw_positions,
+            }),
+        )));
+    }
 
-    Ok(Json(records))
+    Ok(PositionsResponder::Json(Json(PositionsResponse {
+        returned_positions,
+        raw_positions,
+        positions: location_records
+            .into_iter()
+            .map(LocationRecord::from)
+            .collect(),
+        next_cursor,
+    })))
 }
 
 #[options("/auth/token")]
@@ -115,7 +592,7 @@ pub fn get_login_token(
         .build_transaction()
         .read_only()
         .run::<_, diesel::result::Error, _>(move |connection| {
-            if let Ok(found_users) = users
+            if let Ok(found_users) = users_table
                 .filter(username.eq(supplied_username))
                 .load::<User>(connection)
             {
@@ -187,9 +664,17 @@ pub fn get_login_token(
     if let Some(token) = get_token_for_user(
         &login_information.username,
         config.token_audience.clone(),
-        "".to_string(), // TODO: set the correct token issuer
+        config.api_host.clone(),
         &config.encoding_key.clone().unwrap(),
     ) {
+        let refresh_token = issue_refresh_token(user.id, &mut db_connection).map_err(|error| {
+            error!(
+                "Could not store a new refresh token for '{}'. The error was: {}",
+                login_information.username, error
+            );
+            Status::InternalServerError
+        })?;
+
         log_audit_message(
             &mut db_connection,
             AuditLogAction::UserAuthentication,
@@ -199,6 +684,7 @@ pub fn get_login_token(
 
         return Ok(Json(TokenResponse {
             access_token: token,
+            refresh_token,
         }));
     }
 
@@ -206,3 +692,207 @@ pub fn get_login_token(
     // seems to be REALLY wrong
     Err(Status::InternalServerError)
 }
+
+#[options("/auth/refresh")]
+pub fn refresh_login_token_options() -> Status {
+    Status::Ok
+}
+
+#[post("/auth/refresh", data = "<refresh_information>")]
+pub fn refresh_login_token(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    refresh_information: Json<RefreshInformation>,
+    config: &State<BackendConfiguration>,
+    client_ip: Option<IpAddr>,
+) -> Result<Json<TokenResponse>, Status> {
+    use crate::schema::refresh_tokens::dsl::refresh_tokens;
+    use crate::schema::refresh_tokens::{revoked_at, token_hash, user_id};
+
+    let remote_endppoint = client_ip.unwrap_or(IpAddr::from([0, 0, 0, 0])).to_string();
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+
+    // the supplied refresh token is revoked and replaced by a new one in any case, so each refresh
+    // token can only be used once
+    let supplied_token_hash = hash_refresh_token(&refresh_information.refresh_token);
+    let refresh_result = db_connection.transaction::<_, diesel::result::Error, _>(|connection| {
+        let Some(stored_token) = refresh_tokens
+            .filter(token_hash.eq(&supplied_token_hash))
+            .first::<RefreshToken>(connection)
+            .optional()?
+        else {
+            warn!("Received a refresh token which is not known");
+            return Ok(None);
+        };
+
+        let now = Utc::now().naive_utc();
+        if stored_token.revoked_at.is_some() {
+            // a refresh token which was already used is a hint that it was stolen, so we revoke
+            // all refresh tokens of the user to end all sessions
+            warn!(
+                "Received a refresh token of the user {} which was already used. Revoking all of the refresh tokens of the user",
+                stored_token.user_id
+            );
+            diesel::update(
+                refresh_tokens
+                    .filter(user_id.eq(stored_token.user_id))
+                    .filter(revoked_at.is_null()),
+            )
+            .set(revoked_at.eq(now))
+            .execute(connection)?;
+            return Ok(None);
+        }
+        if stored_token.expires_at < now {
+            warn!(
+                "Received an expired refresh token of the user {}",
+                stored_token.user_id
+            );
+            return Ok(None);
+        }
+
+        diesel::update(refresh_tokens.find(stored_token.id))
+            .set(revoked_at.eq(now))
+            .execute(connection)?;
+        let user = users_table
+            .find(stored_token.user_id)
+            .first::<User>(connection)?;
+        let new_refresh_token = issue_refresh_token(user.id, connection)?;
+        Ok(Some((user, new_refresh_token)))
+    });
+
+    let (user, refresh_token) = match refresh_result {
+        Ok(Some(refreshed)) => refreshed,
+        Ok(None) => {
+            log_audit_message(
+                &mut db_connection,
+                AuditLogAction::TokenRefresh,
+                AuditLogResult::Failed,
+                &remote_endppoint,
+            );
+            return Err(Status::Unauthorized);
+        }
+        Err(error) => {
+            error!(
+                "Could not rotate the supplied refresh token. The error was: {}",
+                error
+            );
+            return Err(Status::InternalServerError);
+        }
+    };
+
+    let Some(access_token) = get_token_for_user(
+        &user.username,
+        config.token_audience.clone(),
+        config.api_host.clone(),
+        &config.encoding_key.clone().unwrap(),
+    ) else {
+        return Err(Status::InternalServerError);
+    };
+
+    log_audit_message(
+        &mut db_connection,
+        AuditLogAction::TokenRefresh,
+        AuditLogResult::Successful,
+        &remote_endppoint,
+    );
+
+    Ok(Json(TokenResponse {
+        access_token,
+        refresh_token,
+    }))
+}
+
+#[options("/auth/logout")]
+pub fn logout_options() -> Status {
+    Status::Ok
+}
+
+#[post("/auth/logout", data = "<refresh_information>")]
+pub fn logout(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    refresh_information: Option<Json<RefreshInformation>>,
+    client_ip: Option<IpAddr>,
+) -> Result<Status, Status> {
+    use crate::schema::refresh_tokens::dsl::refresh_tokens;
+    use crate::schema::refresh_tokens::{
+        expires_at as refresh_token_expires_at, revoked_at, token_hash, user_id,
+    };
+    use crate::schema::revoked_access_tokens::dsl::revoked_access_tokens;
+    use crate::schema::revoked_access_tokens::expires_at as access_token_expires_at;
+
+    let remote_endppoint = client_ip.unwrap_or(IpAddr::from([0, 0, 0, 0])).to_string();
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+
+    let now = Utc::now().naive_utc();
+    let logout_result = db_connection.transaction::<_, diesel::result::Error, _>(|connection| {
+        // the access token has to be remembered until it expires, since it would be valid until then
+        diesel::insert_into(crate::schema::revoked_access_tokens::table)
+            .values(&NewRevokedAccessToken {
+                token_id: authenticated_user.token_id.clone(),
+                expires_at: authenticated_user.token_expires_at,
+            })
+            .on_conflict_do_nothing()
+            .execute(connection)?;
+
+        if let Some(refresh_information) = &refresh_information {
+            diesel::update(
+                refresh_tokens
+                    .filter(token_hash.eq(hash_refresh_token(&refresh_information.refresh_token)))
+                    .filter(user_id.eq(authenticated_user.id))
+                    .filter(revoked_at.is_null()),
+            )
+            .set(revoked_at.eq(now))
+            .execute(connection)?;
+        }
+
+        // there is no need to keep tokens which expired anyway
+        diesel::delete(revoked_access_tokens.filter(access_token_expires_at.lt(now)))
+            .execute(connection)?;
+        diesel::delete(refresh_tokens.filter(refresh_token_expires_at.lt(now)))
+            .execute(connection)?;
+        Ok(())
+    });
+
+    if let Err(error) = logout_result {
+        error!(
+            "Could not revoke the tokens of the user '{}'. The error was: {}",
+            authenticated_user.username, error
+        );
+        return Err(Status::InternalServerError);
+    }
+
+    log_audit_message(
+        &mut db_connection,
+        AuditLogAction::UserLogout,
+        AuditLogResult::Successful,
+        &remote_endppoint,
+    );
+
+    Ok(Status::NoContent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_position_cursor_can_be_decoded_after_encoding() {
+        let cursor = PositionCursor {
+            measurement_time: DateTime::from_timestamp_micros(1_700_000_000_123_456)
+                .unwrap()
+                .naive_utc(),
+            id: 42,
+        };
+
+        assert_eq!(PositionCursor::decode(&cursor.encode()), Some(cursor));
+        assert_eq!(PositionCursor::decode("not a cursor"), None);
+        assert_eq!(
+            PositionCursor::decode(&BASE64_URL_SAFE_NO_PAD.encode("1700000000:abc")),
+            None
+        );
+    }
+}
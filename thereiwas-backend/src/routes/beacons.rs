@@ -0,0 +1,134 @@
+use crate::fairings::ThereIWasDatabaseConnection;
+use crate::guards::AuthenticatedUser;
+use crate::models::BeaconSighting;
+use crate::schema::beacon_sightings::dsl::beacon_sightings;
+use crate::schema::beacon_sightings::{beacon_uuid, measurement_time, reporting_device};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use log::error;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{get, options, State};
+use serde::Serialize;
+use std::fmt;
+
+/// The estimated distance to a beacon as reported by iOS (`prox`)
+pub enum BeaconProximity {
+    /// The distance to the beacon could not be determined
+    Unknown,
+    /// The device is physically very close to the beacon
+    Immediate,
+    /// The device is a few meters away from the beacon
+    Near,
+    /// The beacon is far away or the signal is attenuated
+    Far,
+    /// The reported value is not known to the server
+    Other(i32),
+}
+
+impl From<i32> for BeaconProximity {
+    fn from(value: i32) -> Self {
+        match value {
+            0 => BeaconProximity::Unknown,
+            1 => BeaconProximity::Immediate,
+            2 => BeaconProximity::Near,
+            3 => BeaconProximity::Far,
+            _ => BeaconProximity::Other(value),
+        }
+    }
+}
+
+impl fmt::Display for BeaconProximity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BeaconProximity::Unknown => write!(f, "unknown"),
+            BeaconProximity::Immediate => write!(f, "immediate"),
+            BeaconProximity::Near => write!(f, "near"),
+            BeaconProximity::Far => write!(f, "far"),
+            BeaconProximity::Other(value) => write!(f, "unknown ({})", value),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct BeaconSightingRecord {
+    pub description: Option<String>,
+    pub beacon_uuid: String,
+    pub beacon_major: i32,
+    pub beacon_minor: i32,
+    pub proximity: String,
+    pub rssi: i32,
+    pub horizontal_accuracy: Option<i32>,
+    pub measurement_time: i64,
+}
+
+impl From<BeaconSighting> for BeaconSightingRecord {
+    fn from(sighting: BeaconSighting) -> Self {
+        BeaconSightingRecord {
+            description: sighting.description,
+            beacon_uuid: sighting.beacon_uuid,
+            beacon_major: sighting.beacon_major,
+            beacon_minor: sighting.beacon_minor,
+            proximity: BeaconProximity::from(sighting.proximity).to_string(),
+            rssi: sighting.rssi,
+            horizontal_accuracy: sighting.horizontal_accuracy,
+            measurement_time: sighting.measurement_time.and_utc().timestamp(),
+        }
+    }
+}
+
+#[options("/devices/<_device_id>/beacons")]
+pub fn get_beacon_sightings_options(_device_id: i32) -> Status {
+    Status::Ok
+}
+
+#[get("/devices/<device_id>/beacons?<uuid>&<limit>")]
+pub fn get_beacon_sightings(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+    uuid: Option<String>,
+    limit: Option<i64>,
+) -> Result<Json<Vec<BeaconSightingRecord>>, Status> {
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+
+    let mut query = beacon_sightings
+        .filter(reporting_device.eq(device_id))
+        .order_by(measurement_time.desc())
+        .limit(limit.unwrap_or(100).clamp(1, 1000))
+        .into_boxed();
+    if let Some(uuid) = uuid {
+        query = query.filter(beacon_uuid.eq(uuid));
+    }
+
+    let sightings = query
+        .load::<BeaconSighting>(&mut db_connection)
+        .map_err(|error| {
+            error!(
+                "Could not query the beacon sightings of the device {}. The error was: {}",
+                device_id, error
+            );
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(
+        sightings
+            .into_iter()
+            .map(BeaconSightingRecord::from)
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_beacon_proximity_values_are_decoded() {
+        assert_eq!(BeaconProximity::from(1).to_string(), "immediate");
+        assert_eq!(BeaconProximity::from(3).to_string(), "far");
+        assert_eq!(BeaconProximity::from(9).to_string(), "unknown (9)");
+    }
+}
@@ -0,0 +1,322 @@
+use crate::fairings::ThereIWasDatabaseConnection;
+use crate::guards::AuthenticatedUser;
+use crate::models::{DeviceCommand, NewDeviceCommand, Region};
+use crate::schema::device_commands::dsl::device_commands;
+use crate::schema::device_commands::{delivered_at, id as command_id_column, reporting_device};
+use chrono::Utc;
+use diesel::result::DatabaseErrorKind;
+use diesel::result::Error::DatabaseError;
+use diesel::{Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use log::{debug, error};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{get, options, post, State};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+/// The messages the server can send to an OwnTracks device as a response to a HTTP request.
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "_type", rename_all = "lowercase")]
+pub enum OwnTracksMessage {
+    /// A command the device should execute
+    Cmd(CommandMessage),
+    /// Information about a friend (the name and the picture which should be shown)
+    Card(CardMessage),
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "action", rename_all = "camelCase")]
+pub enum CommandMessage {
+    /// Request the device to publish its current location
+    ReportLocation,
+    /// Request the device to report the steps it counted in the supplied period (iOS only). If no
+    /// period is supplied, the app reports the steps of the current day
+    ReportSteps {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        from: Option<i64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        to: Option<i64>,
+    },
+    /// Replace the regions which are monitored by the device. If no waypoints are supplied, the
+    /// regions which are stored on the server for the device will be sent
+    SetWaypoints {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        waypoints: Option<Value>,
+    },
+    /// Change the configuration of the app
+    SetConfiguration { configuration: Map<String, Value> },
+    /// Remove all regions which are monitored by the device
+    ClearWaypoints,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct CardMessage {
+    /// The name which should be shown for the friend.
+    pub name: String,
+    /// A base64 encoded PNG image which should be shown for the friend.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub face: Option<String>,
+    /// The tracker id of the friend the card belongs to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tid: Option<String>,
+    /// The topic of the friend the card belongs to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DeviceCommandRecord {
+    pub id: i32,
+    pub payload: Value,
+    pub created_at: i64,
+    pub delivered_at: Option<i64>,
+}
+
+impl From<DeviceCommand> for DeviceCommandRecord {
+    fn from(command: DeviceCommand) -> Self {
+        DeviceCommandRecord {
+            id: command.id,
+            payload: command.payload,
+            created_at: command.created_at.and_utc().timestamp(),
+            delivered_at: command
+                .delivered_at
+                .map(|time_stamp| time_stamp.and_utc().timestamp()),
+        }
+    }
+}
+
+fn get_waypoints_message_for_device(
+    device_id: i32,
+    db_connection: &mut PgConnection,
+) -> Result<Value, diesel::result::Error> {
+    use crate::schema::regions::dsl::regions;
+    use crate::schema::regions::{created_at, reporting_device as region_reporting_device};
+
+    let stored_regions = regions
+        .filter(region_reporting_device.eq(device_id))
+        .order_by(created_at.asc())
+        .load::<Region>(db_connection)?;
+
+    let waypoints = stored_regions
+        .into_iter()
+        .map(|region| {
+            let mut waypoint = json!({
+                "_type": "waypoint",
+                "desc": region.description,
+                "lat": region.latitude,
+                "lon": region.longitude,
+                "rad": region.radius,
+                "tst": region.created_at.and_utc().timestamp(),
+            });
+            if let Some(rid) = region.region_id {
+                waypoint["rid"] = json!(rid);
+            }
+            if let Some(uuid) = region.beacon_uuid {
+                waypoint["uuid"] = json!(uuid);
+            }
+            if let Some(major) = region.beacon_major {
+                waypoint["major"] = json!(major);
+            }
+            if let Some(minor) = region.beacon_minor {
+                waypoint["minor"] = json!(minor);
+            }
+            waypoint
+        })
+        .collect::<Vec<_>>();
+
+    Ok(json!({
+        "_type": "waypoints",
+        "waypoints": waypoints,
+    }))
+}
+
+/// Queue a command which sends the regions stored on the server to the supplied device. Otherwise
+/// the next waypoints the device publishes would overwrite the regions changed on the server.
+pub fn queue_waypoints_command(
+    device_id: i32,
+    db_connection: &mut PgConnection,
+) -> Result<(), diesel::result::Error> {
+    let waypoints = get_waypoints_message_for_device(device_id, db_connection)?;
+    diesel::insert_into(crate::schema::device_commands::table)
+        .values(&NewDeviceCommand {
+            reporting_device: device_id,
+            payload: json!({
+                "_type": "cmd",
+                "action": "setWaypoints",
+                "waypoints": waypoints,
+            }),
+        })
+        .execute(db_connection)?;
+    Ok(())
+}
+
+/// Get all messages which are still pending for the supplied device and mark them as delivered.
+pub fn take_pending_commands(
+    device_id: i32,
+    db_connection: &mut PgConnection,
+) -> Result<Vec<Value>, diesel::result::Error> {
+    db_connection.transaction::<_, diesel::result::Error, _>(|connection| {
+        let pending_commands = device_commands
+            .filter(reporting_device.eq(device_id))
+            .filter(delivered_at.is_null())
+            .order_by(command_id_column.asc())
+            .load::<DeviceCommand>(connection)?;
+
+        if pending_commands.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let pending_command_ids = pending_commands
+            .iter()
+            .map(|command| command.id)
+            .collect::<Vec<_>>();
+        diesel::update(device_commands.filter(command_id_column.eq_any(pending_command_ids)))
+            .set(delivered_at.eq(Some(Utc::now().naive_utc())))
+            .execute(connection)?;
+
+        debug!(
+            "Delivering {} pending messages to the device {}",
+            pending_commands.len(),
+            device_id
+        );
+        Ok(pending_commands
+            .into_iter()
+            .map(|command| command.payload)
+            .collect())
+    })
+}
+
+#[options("/devices/<_device_id>/commands")]
+pub fn get_device_commands_options(_device_id: i32) -> Status {
+    Status::Ok
+}
+
+#[get("/devices/<device_id>/commands")]
+pub fn get_device_commands(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+) -> Result<Json<Vec<DeviceCommandRecord>>, Status> {
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+
+    let command_records = device_commands
+        .filter(reporting_device.eq(device_id))
+        .order_by(command_id_column.desc())
+        .load::<DeviceCommand>(&mut db_connection)
+        .map_err(|error| {
+            error!(
+                "Could not query the commands of the device {}. The error was: {}",
+                device_id, error
+            );
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(
+        command_records
+            .into_iter()
+            .map(DeviceCommandRecord::from)
+            .collect(),
+    ))
+}
+
+#[post("/devices/<device_id>/commands", data = "<message>")]
+pub fn add_device_command(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+    message: Json<OwnTracksMessage>,
+) -> Result<Json<DeviceCommandRecord>, Status> {
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+
+    let mut message = message.into_inner();
+    match &mut message {
+        OwnTracksMessage::Cmd(CommandMessage::SetWaypoints { waypoints })
+            if waypoints.is_none() =>
+        {
+            *waypoints = Some(
+                get_waypoints_message_for_device(device_id, &mut db_connection).map_err(
+                    |error| {
+                        error!(
+                            "Could not query the regions of the device {}. The error was: {}",
+                            device_id, error
+                        );
+                        Status::InternalServerError
+                    },
+                )?,
+            );
+        }
+        OwnTracksMessage::Cmd(CommandMessage::SetConfiguration { configuration }) => {
+            configuration.insert("_type".to_string(), json!("configuration"));
+        }
+        _ => {}
+    }
+
+    let payload = serde_json::to_value(&message).map_err(|error| {
+        error!(
+            "Could not serialize the command for the device {}. The error was: {}",
+            device_id, error
+        );
+        Status::InternalServerError
+    })?;
+
+    diesel::insert_into(crate::schema::device_commands::table)
+        .values(&NewDeviceCommand {
+            reporting_device: device_id,
+            payload,
+        })
+        .get_result::<DeviceCommand>(&mut db_connection)
+        .map(|command| Json(DeviceCommandRecord::from(command)))
+        .map_err(|error| match error {
+            // users who can access all devices pass the access check even for unknown devices
+            DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => Status::NotFound,
+            _ => {
+                error!(
+                    "Could not store the command for the device {}. The error was: {}",
+                    device_id, error
+                );
+                Status::InternalServerError
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands_are_serialized_as_owntracks_messages() {
+        let report_location =
+            serde_json::to_value(OwnTracksMessage::Cmd(CommandMessage::ReportLocation)).unwrap();
+        assert_eq!(
+            report_location,
+            json!({"_type": "cmd", "action": "reportLocation"})
+        );
+
+        let card = serde_json::to_value(OwnTracksMessage::Card(CardMessage {
+            name: "Jane".to_string(),
+            face: None,
+            tid: Some("JD".to_string()),
+            topic: None,
+        }))
+        .unwrap();
+        assert_eq!(card, json!({"_type": "card", "name": "Jane", "tid": "JD"}));
+    }
+
+    #[test]
+    fn test_set_waypoints_command_can_be_parsed_without_waypoints() {
+        let parsed =
+            serde_json::from_str::<OwnTracksMessage>(r#"{"_type":"cmd","action":"setWaypoints"}"#);
+        assert!(matches!(
+            parsed,
+            Ok(OwnTracksMessage::Cmd(CommandMessage::SetWaypoints {
+                waypoints: None
+            }))
+        ));
+    }
+}
@@ -0,0 +1,339 @@
+use crate::fairings::ThereIWasDatabaseConnection;
+use crate::guards::AuthenticatedUser;
+use crate::models::DeviceStatus;
+use crate::schema::device_status::dsl::device_status;
+use crate::schema::device_status::{received_at, reporting_device};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use log::error;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{get, options, State};
+use serde::Serialize;
+use std::fmt;
+
+// The meanings of the values of the status message of the OwnTracks Android app (`hib`, `bo`,
+// `loc`, `ps` and `wifi`) follow the description of the `status` message in the OwnTracks JSON
+// documentation (https://owntracks.org/booklet/tech/json/). Values which are not described there
+// are reported as unknown, the reported value is always returned next to its meaning.
+
+/// If the Android app is allowed to be hibernated by the system if it was not used for a while (`hib`)
+pub enum AppHibernation {
+    /// The app can be hibernated by the system if it was not used for a while
+    Allowed,
+    /// The app is exempted from being hibernated
+    Exempted,
+    /// The reported value is not known to the server
+    Unknown(i32),
+}
+
+impl From<i32> for AppHibernation {
+    fn from(value: i32) -> Self {
+        match value {
+            0 => AppHibernation::Allowed,
+            1 => AppHibernation::Exempted,
+            _ => AppHibernation::Unknown(value),
+        }
+    }
+}
+
+impl fmt::Display for AppHibernation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppHibernation::Allowed => write!(f, "allowed"),
+            AppHibernation::Exempted => write!(f, "exempted"),
+            AppHibernation::Unknown(value) => write!(f, "unknown ({})", value),
+        }
+    }
+}
+
+/// If the battery optimizations of Android are applied to the app (`bo`)
+pub enum BatteryOptimizations {
+    /// The system restricts the background work of the app to save battery
+    Optimized,
+    /// The app is ignoring the battery optimizations of the system
+    Ignored,
+    /// The reported value is not known to the server
+    Unknown(i32),
+}
+
+impl From<i32> for BatteryOptimizations {
+    fn from(value: i32) -> Self {
+        match value {
+            0 => BatteryOptimizations::Optimized,
+            1 => BatteryOptimizations::Ignored,
+            _ => BatteryOptimizations::Unknown(value),
+        }
+    }
+}
+
+impl fmt::Display for BatteryOptimizations {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BatteryOptimizations::Optimized => write!(f, "optimized"),
+            BatteryOptimizations::Ignored => write!(f, "ignored"),
+            BatteryOptimizations::Unknown(value) => write!(f, "unknown ({})", value),
+        }
+    }
+}
+
+/// The location permission which was granted to the Android app (`loc`)
+pub enum LocationPermission {
+    /// Precise location access, also while the app is in the background
+    FineBackground,
+    /// Approximate location access, also while the app is in the background
+    CoarseBackground,
+    /// Precise location access, only while the app is in the foreground
+    FineForeground,
+    /// Approximate location access, only while the app is in the foreground
+    CoarseForeground,
+    /// The app is not allowed to access the location at all
+    Denied,
+    /// The reported value is not known to the server
+    Unknown(i32),
+}
+
+impl From<i32> for LocationPermission {
+    fn from(value: i32) -> Self {
+        match value {
+            0 => LocationPermission::FineBackground,
+            -1 => LocationPermission::CoarseBackground,
+            -2 => LocationPermission::FineForeground,
+            -3 => LocationPermission::CoarseForeground,
+            -4 => LocationPermission::Denied,
+            _ => LocationPermission::Unknown(value),
+        }
+    }
+}
+
+impl fmt::Display for LocationPermission {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LocationPermission::FineBackground => write!(f, "fine_background"),
+            LocationPermission::CoarseBackground => write!(f, "coarse_background"),
+            LocationPermission::FineForeground => write!(f, "fine_foreground"),
+            LocationPermission::CoarseForeground => write!(f, "coarse_foreground"),
+            LocationPermission::Denied => write!(f, "denied"),
+            LocationPermission::Unknown(value) => write!(f, "unknown ({})", value),
+        }
+    }
+}
+
+/// If the power save mode of Android is enabled (`ps`)
+pub enum PowerSaveMode {
+    /// The power save mode is disabled
+    Disabled,
+    /// The power save mode is enabled
+    Enabled,
+    /// The reported value is not known to the server
+    Unknown(i32),
+}
+
+impl From<i32> for PowerSaveMode {
+    fn from(value: i32) -> Self {
+        match value {
+            0 => PowerSaveMode::Disabled,
+            1 => PowerSaveMode::Enabled,
+            _ => PowerSaveMode::Unknown(value),
+        }
+    }
+}
+
+impl fmt::Display for PowerSaveMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PowerSaveMode::Disabled => write!(f, "disabled"),
+            PowerSaveMode::Enabled => write!(f, "enabled"),
+            PowerSaveMode::Unknown(value) => write!(f, "unknown ({})", value),
+        }
+    }
+}
+
+/// If the WiFi of the Android device is enabled (`wifi`)
+pub enum WifiState {
+    /// The WiFi is disabled
+    Disabled,
+    /// The WiFi is enabled
+    Enabled,
+    /// The reported value is not known to the server
+    Unknown(i32),
+}
+
+impl From<i32> for WifiState {
+    fn from(value: i32) -> Self {
+        match value {
+            0 => WifiState::Disabled,
+            1 => WifiState::Enabled,
+            _ => WifiState::Unknown(value),
+        }
+    }
+}
+
+impl fmt::Display for WifiState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WifiState::Disabled => write!(f, "disabled"),
+            WifiState::Enabled => write!(f, "enabled"),
+            WifiState::Unknown(value) => write!(f, "unknown ({})", value),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct AppleStatusRecord {
+    pub altimeter_authorization_status: Option<String>,
+    pub altimeter_is_relative_altitude_available: Option<bool>,
+    pub background_refresh_status: Option<String>,
+    pub device_identifier_for_vendor: Option<String>,
+    pub device_model: Option<String>,
+    pub device_system_name: Option<String>,
+    pub device_system_version: Option<String>,
+    pub device_user_interface_idiom: Option<String>,
+    pub locale: Option<String>,
+    pub locale_uses_metric_system: Option<bool>,
+    pub location_manager_authorization_status: Option<String>,
+    pub app_version: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct AndroidStatusValue {
+    /// The value as it was reported by the app.
+    pub value: i32,
+    /// The meaning of the reported value.
+    pub meaning: String,
+}
+
+impl AndroidStatusValue {
+    fn decode<T: From<i32> + fmt::Display>(value: i32) -> Self {
+        AndroidStatusValue {
+            value,
+            meaning: T::from(value).to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct AndroidStatusRecord {
+    pub app_hibernation: AndroidStatusValue,
+    pub battery_optimizations: AndroidStatusValue,
+    pub location_permission: AndroidStatusValue,
+    pub power_save_mode: AndroidStatusValue,
+    pub wifi_state: AndroidStatusValue,
+}
+
+#[derive(Serialize)]
+pub struct DeviceStatusRecord {
+    pub request_id: Option<String>,
+    pub received_at: i64,
+    pub ios: Option<AppleStatusRecord>,
+    pub android: Option<AndroidStatusRecord>,
+}
+
+impl From<DeviceStatus> for DeviceStatusRecord {
+    fn from(status: DeviceStatus) -> Self {
+        let is_ios_status = status.app_version.is_some();
+        let ios = is_ios_status.then_some(AppleStatusRecord {
+            altimeter_authorization_status: status.altimeter_authorization_status,
+            altimeter_is_relative_altitude_available: status
+                .altimeter_is_relative_altitude_available,
+            background_refresh_status: status.background_refresh_status,
+            device_identifier_for_vendor: status.device_identifier_for_vendor,
+            device_model: status.device_model,
+            device_system_name: status.device_system_name,
+            device_system_version: status.device_system_version,
+            device_user_interface_idiom: status.device_user_interface_idiom,
+            locale: status.locale,
+            locale_uses_metric_system: status.locale_uses_metric_system,
+            location_manager_authorization_status: status.location_manager_authorization_status,
+            app_version: status.app_version,
+        });
+        let android = match (
+            status.app_hibernation,
+            status.battery_optimizations,
+            status.location_permission,
+            status.power_save,
+            status.wifi_state,
+        ) {
+            (Some(hib), Some(bo), Some(loc), Some(ps), Some(wifi)) => Some(AndroidStatusRecord {
+                app_hibernation: AndroidStatusValue::decode::<AppHibernation>(hib),
+                battery_optimizations: AndroidStatusValue::decode::<BatteryOptimizations>(bo),
+                location_permission: AndroidStatusValue::decode::<LocationPermission>(loc),
+                power_save_mode: AndroidStatusValue::decode::<PowerSaveMode>(ps),
+                wifi_state: AndroidStatusValue::decode::<WifiState>(wifi),
+            }),
+            _ => None,
+        };
+
+        DeviceStatusRecord {
+            request_id: status.request_id,
+            received_at: status.received_at.and_utc().timestamp(),
+            ios,
+            android,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DeviceStatusHistory {
+    pub latest: Option<DeviceStatusRecord>,
+    pub history: Vec<DeviceStatusRecord>,
+}
+
+#[options("/devices/<_device_id>/status")]
+pub fn get_device_status_options(_device_id: i32) -> Status {
+    Status::Ok
+}
+
+#[get("/devices/<device_id>/status?<limit>")]
+pub fn get_device_status(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+    limit: Option<i64>,
+) -> Result<Json<DeviceStatusHistory>, Status> {
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+
+    let status_reports = device_status
+        .filter(reporting_device.eq(device_id))
+        .order_by(received_at.desc())
+        .limit(limit.unwrap_or(100).clamp(1, 1000))
+        .load::<DeviceStatus>(&mut db_connection)
+        .map_err(|error| {
+            error!(
+                "Could not query the status reports of the device {}. The error was: {}",
+                device_id, error
+            );
+            Status::InternalServerError
+        })?;
+
+    let mut history = status_reports
+        .into_iter()
+        .map(DeviceStatusRecord::from)
+        .collect::<Vec<_>>();
+    let latest = if history.is_empty() {
+        None
+    } else {
+        Some(history.remove(0))
+    };
+
+    Ok(Json(DeviceStatusHistory { latest, history }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_android_status_values_are_decoded() {
+        assert_eq!(AppHibernation::from(1).to_string(), "exempted");
+        assert_eq!(BatteryOptimizations::from(0).to_string(), "optimized");
+        assert_eq!(LocationPermission::from(-2).to_string(), "fine_foreground");
+        assert_eq!(LocationPermission::from(7).to_string(), "unknown (7)");
+
+        let wifi_state = AndroidStatusValue::decode::<WifiState>(3);
+        assert_eq!(wifi_state.value, 3);
+        assert_eq!(wifi_state.meaning, "unknown (3)");
+    }
+}
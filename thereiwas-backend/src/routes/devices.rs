@@ -0,0 +1,530 @@
+use crate::fairings::ThereIWasDatabaseConnection;
+use crate::guards::ManageDevicesPermission;
+use crate::models::{ClientToken, DeviceChange, NewClientToken};
+use crate::schema::client_tokens::dsl::client_tokens;
+use crate::schema::client_tokens::{created_at, id as device_id_column, revoked_at, secret_hash};
+use crate::{
+    generate_client_id, generate_client_secret, hash_client_secret, log_audit_message,
+    AuditLogAction, AuditLogResult,
+};
+use chrono::Utc;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use log::{error, info};
+use reqwest::blocking::Client;
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use rocket::http::Status;
+use rocket::response::status::Created;
+use rocket::serde::json::Json;
+use rocket::{delete, get, options, post, put, State};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::net::IpAddr;
+
+/// The maximal number of characters of a device description (see the `client_tokens` table).
+const MAXIMUM_DESCRIPTION_LENGTH: usize = 128;
+
+/// The maximal number of characters of a health callback URL (see the `client_tokens` table).
+const MAXIMUM_HEALTH_CALLBACK_URL_LENGTH: usize = 255;
+
+#[derive(Serialize)]
+pub struct DeviceRecord {
+    pub id: i32,
+    pub client_id: String,
+    pub user_id: Option<i32>,
+    pub description: Option<String>,
+    pub health_callback_url: Option<String>,
+    pub reported_user: Option<String>,
+    pub reported_device: Option<String>,
+    pub created_at: i64,
+    pub revoked_at: Option<i64>,
+}
+
+impl From<ClientToken> for DeviceRecord {
+    fn from(client_token: ClientToken) -> Self {
+        DeviceRecord {
+            id: client_token.id,
+            client_id: client_token.client,
+            user_id: client_token.user_id,
+            description: client_token.description,
+            health_callback_url: client_token.health_callback_url,
+            reported_user: client_token.reported_user,
+            reported_device: client_token.reported_device,
+            created_at: client_token.created_at.and_utc().timestamp(),
+            revoked_at: client_token
+                .revoked_at
+                .map(|revoked| revoked.and_utc().timestamp()),
+        }
+    }
+}
+
+/// The credentials of a device. The secret is only returned once and can not be looked up again.
+#[derive(Serialize)]
+pub struct DeviceCredentialsRecord {
+    pub device: DeviceRecord,
+    pub client_secret: String,
+}
+
+#[derive(Deserialize)]
+pub struct DeviceInformation {
+    /// A human-readable name of the device.
+    description: Option<String>,
+    /// The URL which should be called after each successfully stored location (if any).
+    health_callback_url: Option<String>,
+}
+
+/// The changes of a device. Fields which are missing in the request are kept as they are, fields
+/// which are `null` are removed from the device.
+#[derive(Deserialize)]
+pub struct DeviceChangeRequest {
+    /// A human-readable name of the device.
+    #[serde(default, deserialize_with = "deserialize_present_field")]
+    description: Option<Option<String>>,
+    /// The URL which should be called after each successfully stored location (if any).
+    #[serde(default, deserialize_with = "deserialize_present_field")]
+    health_callback_url: Option<Option<String>>,
+}
+
+/// Deserialize a field which is present in the request, so that a `null` value can be told apart
+/// from a missing field (which is `None` by `#[serde(default)]`).
+fn deserialize_present_field<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Option<String>>, D::Error> {
+    Option::<String>::deserialize(deserializer).map(Some)
+}
+
+fn is_valid_description(description: &str) -> bool {
+    !description.is_empty() && description.chars().count() <= MAXIMUM_DESCRIPTION_LENGTH
+}
+
+/// Check if the supplied address can be reached from the internet. The server requests the health
+/// callback URL after every message of a device, so it must not be pointed at internal hosts.
+fn is_public_ip_address(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            let [first_octet, second_octet, ..] = address.octets();
+            !(address.is_unspecified()
+                || address.is_loopback()
+                || address.is_private()
+                || address.is_link_local()
+                || address.is_broadcast()
+                || address.is_multicast()
+                || first_octet == 0
+                // the shared address space of carrier-grade NATs (100.64.0.0/10)
+                || (first_octet == 100 && second_octet & 0xc0 == 64))
+        }
+        IpAddr::V6(address) => match address.to_ipv4_mapped() {
+            Some(mapped_address) => is_public_ip_address(IpAddr::V4(mapped_address)),
+            None => {
+                let first_segment = address.segments()[0];
+                !(address.is_unspecified()
+                    || address.is_loopback()
+                    || address.is_multicast()
+                    // unique local (fc00::/7) and link-local (fe80::/10) addresses
+                    || first_segment & 0xfe00 == 0xfc00
+                    || first_segment & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Check the scheme, the length and the host of a health callback URL. Host names are only
+/// resolved when the URL is called (see `get_health_callback_client`).
+fn is_valid_health_callback_url(url: &str) -> bool {
+    if url.chars().count() > MAXIMUM_HEALTH_CALLBACK_URL_LENGTH {
+        return false;
+    }
+    let Ok(url) = Url::parse(url) else {
+        return false;
+    };
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return false;
+    }
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    // IPv6 addresses are enclosed in brackets in URLs
+    match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(address) => is_public_ip_address(address),
+        Err(_) => {
+            let domain = host.trim_end_matches('.').to_ascii_lowercase();
+            domain != "localhost" && !domain.ends_with(".localhost")
+        }
+    }
+}
+
+/// Get a client for calling the supplied health callback URL. The host of the URL is resolved
+/// beforehand and the client only connects to these addresses, so a host name which resolves to an
+/// internal address is rejected as well. Redirects are not followed for the same reason.
+pub(crate) fn get_health_callback_client(url: &str) -> Result<Client, String> {
+    if !is_valid_health_callback_url(url) {
+        return Err("The URL is not a valid health callback URL".to_string());
+    }
+    let url = Url::parse(url).map_err(|error| error.to_string())?;
+    let addresses = url
+        .socket_addrs(|| None)
+        .map_err(|error| error.to_string())?;
+    if addresses.is_empty()
+        || !addresses
+            .iter()
+            .all(|address| is_public_ip_address(address.ip()))
+    {
+        return Err("The host of the URL resolves to an internal address".to_string());
+    }
+
+    let mut client_builder = Client::builder().redirect(Policy::none());
+    if let Some(domain) = url.domain() {
+        client_builder = client_builder.resolve_to_addrs(domain, &addresses);
+    }
+    client_builder.build().map_err(|error| error.to_string())
+}
+
+impl DeviceInformation {
+    fn is_valid(&self) -> bool {
+        self.description
+            .as_deref()
+            .map_or(true, is_valid_description)
+            && self
+                .health_callback_url
+                .as_deref()
+                .map_or(true, is_valid_health_callback_url)
+    }
+}
+
+impl DeviceChangeRequest {
+    fn is_valid(&self) -> bool {
+        self.description
+            .as_ref()
+            .and_then(Option::as_deref)
+            .map_or(true, is_valid_description)
+            && self
+                .health_callback_url
+                .as_ref()
+                .and_then(Option::as_deref)
+                .map_or(true, is_valid_health_callback_url)
+    }
+}
+
+#[options("/devices")]
+pub fn get_devices_options() -> Status {
+    Status::Ok
+}
+
+#[options("/devices/<_device_id>")]
+pub fn get_device_options(_device_id: i32) -> Status {
+    Status::Ok
+}
+
+#[options("/devices/<_device_id>/secret")]
+pub fn get_device_secret_options(_device_id: i32) -> Status {
+    Status::Ok
+}
+
+#[get("/devices")]
+pub fn get_devices(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: ManageDevicesPermission,
+) -> Result<Json<Vec<DeviceRecord>>, Status> {
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+
+    let mut device_query = client_tokens.order_by(created_at.asc()).into_boxed();
+    if let Some(accessible_devices) = authenticated_user
+        .0
+        .get_accessible_devices(&mut db_connection)
+        .map_err(|error| {
+            error!(
+                "Could not query the devices of the user '{}'. The error was: {}",
+                authenticated_user.0.username, error
+            );
+            Status::InternalServerError
+        })?
+    {
+        device_query = device_query.filter(device_id_column.eq_any(accessible_devices));
+    }
+
+    let registered_devices = device_query
+        .load::<ClientToken>(&mut db_connection)
+        .map_err(|error| {
+            error!(
+                "Could not query the registered devices. The error was: {}",
+                error
+            );
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(
+        registered_devices
+            .into_iter()
+            .map(DeviceRecord::from)
+            .collect(),
+    ))
+}
+
+#[post("/devices", data = "<device_information>")]
+pub fn add_device(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: ManageDevicesPermission,
+    device_information: Json<DeviceInformation>,
+    client_ip: Option<IpAddr>,
+) -> Result<Created<Json<DeviceCredentialsRecord>>, Status> {
+    let remote_endppoint = client_ip.unwrap_or(IpAddr::from([0, 0, 0, 0])).to_string();
+
+    if !device_information.is_valid() {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let client_secret = generate_client_secret();
+    let hashed_secret = hash_client_secret(&client_secret).ok_or(Status::InternalServerError)?;
+
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+
+    let device_information = device_information.into_inner();
+    let registered_device = diesel::insert_into(client_tokens)
+        .values(&NewClientToken {
+            client: generate_client_id(),
+            secret_hash: hashed_secret,
+            description: device_information.description,
+            health_callback_url: device_information.health_callback_url,
+            user_id: Some(authenticated_user.0.id),
+        })
+        .get_result::<ClientToken>(&mut db_connection)
+        .map_err(|error| {
+            error!("Could not register a new device. The error was: {}", error);
+            Status::InternalServerError
+        })?;
+
+    info!(
+        "The device {} was registered by '{}'",
+        registered_device.id, authenticated_user.0.username
+    );
+    log_audit_message(
+        &mut db_connection,
+        AuditLogAction::DeviceRegistration,
+        AuditLogResult::Successful,
+        &remote_endppoint,
+    );
+
+    Ok(
+        Created::new(format!("/v1/devices/{}", registered_device.id)).body(Json(
+            DeviceCredentialsRecord {
+                device: DeviceRecord::from(registered_device),
+                client_secret,
+            },
+        )),
+    )
+}
+
+/// Change the description and the health callback URL of a device. Only the fields which are
+/// part of the request are changed.
+#[put("/devices/<device_id>", data = "<device_change>")]
+pub fn update_device(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: ManageDevicesPermission,
+    device_id: i32,
+    device_change: Json<DeviceChangeRequest>,
+    client_ip: Option<IpAddr>,
+) -> Result<Json<DeviceRecord>, Status> {
+    let remote_endppoint = client_ip.unwrap_or(IpAddr::from([0, 0, 0, 0])).to_string();
+
+    if !device_change.is_valid() {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user
+        .0
+        .ensure_device_access(device_id, &mut db_connection)?;
+
+    let device_change = device_change.into_inner();
+    let update_result =
+        if device_change.description.is_none() && device_change.health_callback_url.is_none() {
+            // there is nothing to change, so the device is just returned as it is
+            client_tokens
+                .find(device_id)
+                .first::<ClientToken>(&mut db_connection)
+        } else {
+            diesel::update(client_tokens.find(device_id))
+                .set(&DeviceChange {
+                    description: device_change.description,
+                    health_callback_url: device_change.health_callback_url,
+                })
+                .get_result::<ClientToken>(&mut db_connection)
+        };
+    let updated_device = update_result
+        .optional()
+        .map_err(|error| {
+            error!(
+                "Could not update the device {}. The error was: {}",
+                device_id, error
+            );
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+
+    info!(
+        "The device {} was changed by '{}'",
+        device_id, authenticated_user.0.username
+    );
+    log_audit_message(
+        &mut db_connection,
+        AuditLogAction::DeviceUpdate,
+        AuditLogResult::Successful,
+        &remote_endppoint,
+    );
+
+    Ok(Json(DeviceRecord::from(updated_device)))
+}
+
+#[post("/devices/<device_id>/secret")]
+pub fn rotate_device_secret(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: ManageDevicesPermission,
+    device_id: i32,
+    client_ip: Option<IpAddr>,
+) -> Result<Json<DeviceCredentialsRecord>, Status> {
+    let remote_endppoint = client_ip.unwrap_or(IpAddr::from([0, 0, 0, 0])).to_string();
+
+    let client_secret = generate_client_secret();
+    let hashed_secret = hash_client_secret(&client_secret).ok_or(Status::InternalServerError)?;
+
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user
+        .0
+        .ensure_device_access(device_id, &mut db_connection)?;
+
+    // a revoked device stays revoked, it has to be registered again instead
+    let updated_device = diesel::update(client_tokens.find(device_id).filter(revoked_at.is_null()))
+        .set(secret_hash.eq(hashed_secret))
+        .get_result::<ClientToken>(&mut db_connection)
+        .optional()
+        .map_err(|error| {
+            error!(
+                "Could not rotate the secret of the device {}. The error was: {}",
+                device_id, error
+            );
+            Status::InternalServerError
+        })?
+        .ok_or(Status::NotFound)?;
+
+    info!(
+        "The secret of the device {} was rotated by '{}'",
+        device_id, authenticated_user.0.username
+    );
+    log_audit_message(
+        &mut db_connection,
+        AuditLogAction::DeviceSecretRotation,
+        AuditLogResult::Successful,
+        &remote_endppoint,
+    );
+
+    Ok(Json(DeviceCredentialsRecord {
+        device: DeviceRecord::from(updated_device),
+        client_secret,
+    }))
+}
+
+#[delete("/devices/<device_id>")]
+pub fn revoke_device(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: ManageDevicesPermission,
+    device_id: i32,
+    client_ip: Option<IpAddr>,
+) -> Result<Status, Status> {
+    let remote_endppoint = client_ip.unwrap_or(IpAddr::from([0, 0, 0, 0])).to_string();
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user
+        .0
+        .ensure_device_access(device_id, &mut db_connection)?;
+
+    // the device is only marked as revoked, so the locations it reported before are kept
+    match diesel::update(client_tokens.find(device_id).filter(revoked_at.is_null()))
+        .set(revoked_at.eq(Utc::now().naive_utc()))
+        .execute(&mut db_connection)
+    {
+        Ok(0) => Err(Status::NotFound),
+        Ok(_) => {
+            info!(
+                "The device {} was revoked by '{}'",
+                device_id, authenticated_user.0.username
+            );
+            log_audit_message(
+                &mut db_connection,
+                AuditLogAction::DeviceRevocation,
+                AuditLogResult::Successful,
+                &remote_endppoint,
+            );
+            Ok(Status::NoContent)
+        }
+        Err(error) => {
+            error!(
+                "Could not revoke the device {}. The error was: {}",
+                device_id, error
+            );
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_information_is_validated() {
+        let device_information = |description: Option<&str>, url: Option<&str>| DeviceInformation {
+            description: description.map(str::to_string),
+            health_callback_url: url.map(str::to_string),
+        };
+
+        assert!(device_information(None, None).is_valid());
+        assert!(device_information(Some("Phone"), Some("https://hc-ping.com/abc")).is_valid());
+        assert!(!device_information(Some(""), None).is_valid());
+        assert!(!device_information(None, Some("ftp://example.com")).is_valid());
+        assert!(!device_information(Some(&"a".repeat(129)), None).is_valid());
+    }
+
+    #[test]
+    fn test_health_callback_urls_of_internal_hosts_are_rejected() {
+        assert!(is_valid_health_callback_url("https://hc-ping.com/abc"));
+        assert!(is_valid_health_callback_url(
+            "http://93.184.215.14:8080/ping"
+        ));
+        assert!(!is_valid_health_callback_url(
+            "http://169.254.169.254/latest"
+        ));
+        assert!(!is_valid_health_callback_url("http://127.0.0.1/"));
+        assert!(!is_valid_health_callback_url("http://2130706433/"));
+        assert!(!is_valid_health_callback_url("http://10.0.0.1/"));
+        assert!(!is_valid_health_callback_url("http://192.168.1.1/"));
+        assert!(!is_valid_health_callback_url("http://100.64.0.1/"));
+        assert!(!is_valid_health_callback_url("http://localhost:8000/"));
+        assert!(!is_valid_health_callback_url("http://[::1]/"));
+        assert!(!is_valid_health_callback_url("http://[fd00::1]/"));
+        assert!(!is_valid_health_callback_url("http://[::ffff:127.0.0.1]/"));
+        assert!(!is_valid_health_callback_url("file:///etc/passwd"));
+    }
+
+    #[test]
+    fn test_missing_fields_of_device_changes_are_kept() {
+        let device_change =
+            serde_json::from_str::<DeviceChangeRequest>(r#"{"description": "Phone"}"#).unwrap();
+        assert_eq!(device_change.description, Some(Some("Phone".to_string())));
+        assert_eq!(device_change.health_callback_url, None);
+
+        let device_change =
+            serde_json::from_str::<DeviceChangeRequest>(r#"{"health_callback_url": null}"#)
+                .unwrap();
+        assert_eq!(device_change.description, None);
+        assert_eq!(device_change.health_callback_url, Some(None));
+        assert!(device_change.is_valid());
+    }
+}
@@ -0,0 +1,978 @@
+use super::parse_timestamp;
+use crate::fairings::ThereIWasDatabaseConnection;
+use crate::guards::AuthenticatedUser;
+use crate::models::{Location, WifiAccessPoint};
+use crate::schema::locations::dsl::locations;
+use crate::schema::locations::{id as location_id, measurement_time, reporting_device};
+use crate::schema::locations_to_wifi_access_points::dsl::locations_to_wifi_access_points;
+use crate::schema::locations_to_wifi_access_points::location_id as wifi_location_id;
+use crate::schema::wifi_access_points::dsl::wifi_access_points;
+use chrono::{NaiveDate, NaiveDateTime};
+use chrono_tz::Tz;
+use diesel::{
+    BoolExpressionMethods, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl, SelectableHelper,
+};
+use log::error;
+use rocket::http::{ContentType, Header, Status};
+use rocket::response::stream::TextStream;
+use rocket::tokio::sync::mpsc;
+use rocket::{get, options, Responder, State};
+use std::collections::HashMap;
+use std::io::{Cursor, Write};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// The number of locations which are loaded from the database at once while streaming an export.
+const EXPORT_BATCH_SIZE: i64 = 1000;
+
+/// The number of finished days of a KMZ export which can wait for their compression.
+const KMZ_PENDING_DAYS: usize = 4;
+
+/// The comment which ends an XML export if not all locations could be loaded.
+const INCOMPLETE_XML_EXPORT_MARKER: &str =
+    "<!-- the export is incomplete since not all locations could be loaded -->\n";
+
+/// The last line of a CSV export if not all locations could be loaded. Unlike the XML exports, a
+/// truncated CSV file is still valid, so the missing locations are pointed out by this line.
+const INCOMPLETE_CSV_EXPORT_MARKER: &str =
+    "# the export is incomplete since not all locations could be loaded\n";
+
+/// The namespace of the GPX extension elements which carry the values GPX has no elements for.
+const GPX_EXTENSION_NAMESPACE: &str = "https://github.com/flying7eleven/thereiwas/gpx/1";
+
+/// A file with exported data which should be downloaded by the browser.
+#[derive(Responder)]
+pub struct ExportResponse<R> {
+    inner: R,
+    content_type: ContentType,
+    content_disposition: Header<'static>,
+}
+
+impl<R> ExportResponse<R> {
+    fn new(inner: R, content_type: ContentType, file_name: String) -> Self {
+        ExportResponse {
+            inner,
+            content_type,
+            content_disposition: Header::new(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", file_name),
+            ),
+        }
+    }
+}
+
+/// Loads the locations of a device in a time range batch by batch, so exports of many years do
+/// not have to be kept in memory.
+struct LocationBatches {
+    db_connection_pool: ThereIWasDatabaseConnection,
+    device_id: i32,
+    from: Option<NaiveDateTime>,
+    to: Option<NaiveDateTime>,
+    last_location: Option<(NaiveDateTime, i32)>,
+    is_exhausted: bool,
+}
+
+impl LocationBatches {
+    fn new(
+        db_connection_pool: ThereIWasDatabaseConnection,
+        device_id: i32,
+        from: Option<NaiveDateTime>,
+        to: Option<NaiveDateTime>,
+    ) -> Self {
+        LocationBatches {
+            db_connection_pool,
+            device_id,
+            from,
+            to,
+            last_location: None,
+            is_exhausted: false,
+        }
+    }
+
+    /// Run a query on a blocking task, so loading the data does not block the streaming of the
+    /// response.
+    async fn run_blocking<T, F>(&self, query: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut PgConnection) -> Result<T, diesel::result::Error> + Send + 'static,
+    {
+        let db_connection_pool = self.db_connection_pool.clone();
+        rocket::tokio::task::spawn_blocking(move || {
+            let mut db_connection = db_connection_pool
+                .get()
+                .map_err(|error| error.to_string())?;
+            query(&mut db_connection).map_err(|error| error.to_string())
+        })
+        .await
+        .map_err(|error| error.to_string())
+        .and_then(|query_result| query_result)
+    }
+
+    fn load_batch(
+        &self,
+        db_connection: &mut PgConnection,
+    ) -> Result<Vec<Location>, diesel::result::Error> {
+        let mut location_query = locations
+            .filter(reporting_device.eq(self.device_id))
+            .order_by((measurement_time.asc(), location_id.asc()))
+            .limit(EXPORT_BATCH_SIZE)
+            .into_boxed();
+        if let Some(from) = self.from {
+            location_query = location_query.filter(measurement_time.ge(from));
+        }
+        if let Some(to) = self.to {
+            location_query = location_query.filter(measurement_time.le(to));
+        }
+        if let Some((last_measurement_time, last_id)) = self.last_location {
+            location_query = location_query.filter(
+                measurement_time
+                    .gt(last_measurement_time)
+                    .or(measurement_time
+                        .eq(last_measurement_time)
+                        .and(location_id.gt(last_id))),
+            );
+        }
+
+        location_query.load::<Location>(db_connection)
+    }
+
+    /// Get the next batch of locations (ordered by their measurement time) or `None` if all
+    /// locations were loaded. Errors are logged and have to end the export, since the response is
+    /// already partially sent and would look complete otherwise.
+    async fn next_batch(&mut self) -> Result<Option<Vec<Location>>, Status> {
+        if self.is_exhausted {
+            return Ok(None);
+        }
+
+        let batches = LocationBatches {
+            db_connection_pool: self.db_connection_pool.clone(),
+            ..*self
+        };
+        let batch = self
+            .run_blocking(move |db_connection| batches.load_batch(db_connection))
+            .await
+            .map_err(|error| {
+                error!(
+                    "Could not load the locations of the device {} for an export. The error was: {}",
+                    self.device_id, error
+                );
+                Status::InternalServerError
+            })?;
+
+        self.is_exhausted = (batch.len() as i64) < EXPORT_BATCH_SIZE;
+        self.last_location = batch
+            .last()
+            .map(|location| (location.measurement_time, location.id));
+        if batch.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(batch))
+    }
+
+    /// Get the WiFi access points the device was connected to for each of the supplied locations.
+    /// Locations without a WiFi access point are not part of the result. Like for the batches,
+    /// errors are logged and have to end the export.
+    async fn get_wifi_access_points(
+        &self,
+        batch: &[Location],
+    ) -> Result<HashMap<i32, Vec<WifiAccessPoint>>, Status> {
+        let location_ids: Vec<i32> = batch.iter().map(|location| location.id).collect();
+        let networks = self
+            .run_blocking(move |db_connection| {
+                locations_to_wifi_access_points
+                    .inner_join(wifi_access_points)
+                    .filter(wifi_location_id.eq_any(location_ids))
+                    .select((wifi_location_id, WifiAccessPoint::as_select()))
+                    .load::<(i32, WifiAccessPoint)>(db_connection)
+            })
+            .await
+            .map_err(|error| {
+                error!(
+                    "Could not load the WiFi networks of the device {} for an export. The error was: {}",
+                    self.device_id, error
+                );
+                Status::InternalServerError
+            })?;
+
+        let mut access_points = HashMap::<i32, Vec<WifiAccessPoint>>::new();
+        for (location, access_point) in networks {
+            access_points
+                .entry(location)
+                .or_default()
+                .push(access_point);
+        }
+        Ok(access_points)
+    }
+}
+
+/// Escape the characters which are not allowed in the text of XML elements or attributes.
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn format_gpx_track_point(location: &Location) -> String {
+    let mut track_point = format!(
+        "<trkpt lat=\"{}\" lon=\"{}\">",
+        location.latitude, location.longitude
+    );
+    if let Some(altitude) = location.altitude {
+        track_point.push_str(&format!("<ele>{}</ele>", altitude));
+    }
+    track_point.push_str(&format!(
+        "<time>{}</time><extensions>",
+        location
+            .measurement_time
+            .and_utc()
+            .format("%Y-%m-%dT%H:%M:%SZ")
+    ));
+    if let Some(horizontal_accuracy) = location.horizontal_accuracy {
+        track_point.push_str(&format!(
+            "<thereiwas:horizontal_accuracy>{}</thereiwas:horizontal_accuracy>",
+            horizontal_accuracy
+        ));
+    }
+    if let Some(vertical_accuracy) = location.vertical_accuracy {
+        track_point.push_str(&format!(
+            "<thereiwas:vertical_accuracy>{}</thereiwas:vertical_accuracy>",
+            vertical_accuracy
+        ));
+    }
+    if let Some(barometric_pressure) = location.barometric_pressure {
+        track_point.push_str(&format!(
+            "<thereiwas:barometric_pressure>{}</thereiwas:barometric_pressure>",
+            barometric_pressure
+        ));
+    }
+    track_point.push_str(&format!(
+        "<thereiwas:report_trigger>{}</thereiwas:report_trigger></extensions></trkpt>\n",
+        escape_xml(&location.report_trigger)
+    ));
+    track_point
+}
+
+/// Get the names of the WiFi networks for their placemarks. Hidden networks are reported without a
+/// SSID, so they are named by their BSSID instead.
+fn get_wifi_network_names(access_points: Option<Vec<WifiAccessPoint>>) -> Vec<String> {
+    access_points
+        .unwrap_or_default()
+        .into_iter()
+        .map(|access_point| {
+            if access_point.ssid.is_empty() {
+                access_point.bssid
+            } else {
+                access_point.ssid
+            }
+        })
+        .collect()
+}
+
+fn format_kml_time(time: NaiveDateTime) -> String {
+    time.and_utc().format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// Writes the KML document of an export day by day. The locations of a day are kept until the
+/// next day starts, since the line, the track and the WiFi placemarks of a day are separate
+/// elements.
+struct KmlDocument {
+    current_day: Option<NaiveDate>,
+    day_locations: Vec<(Location, Vec<String>)>,
+}
+
+impl KmlDocument {
+    fn new() -> Self {
+        KmlDocument {
+            current_day: None,
+            day_locations: vec![],
+        }
+    }
+
+    fn get_header(device_id: i32) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<kml xmlns=\"http://www.opengis.net/kml/2.2\" xmlns:gx=\"http://www.google.com/kml/ext/2.2\">\n<Document><name>thereiwas device {}</name>\n<Style id=\"track\"><LineStyle><color>ffff7800</color><width>3</width></LineStyle></Style>\n<Style id=\"wifi\"><IconStyle><Icon><href>http://maps.google.com/mapfiles/kml/shapes/placemark_circle.png</href></Icon></IconStyle></Style>\n",
+            device_id
+        )
+    }
+
+    /// Add a location (with the names of the WiFi networks the device was connected to) to the
+    /// document. If the location starts a new day, the previous day is returned.
+    fn push(&mut self, location: Location, wifi_networks: Vec<String>) -> Option<String> {
+        let day = location.measurement_time.date();
+        let finished_day = if self.current_day != Some(day) {
+            self.format_day()
+        } else {
+            None
+        };
+        self.current_day = Some(day);
+        self.day_locations.push((location, wifi_networks));
+        finished_day
+    }
+
+    /// Get the remaining day and the end of the document.
+    fn finish(&mut self) -> String {
+        let mut remaining_document = self.format_day().unwrap_or_default();
+        remaining_document.push_str("</Document></kml>\n");
+        remaining_document
+    }
+
+    fn format_day(&mut self) -> Option<String> {
+        let day = self.current_day?.format("%Y-%m-%d");
+        let day_locations = std::mem::take(&mut self.day_locations);
+
+        let mut folder = format!("<Folder><name>{}</name>\n", day);
+
+        // a line needs at least two coordinates
+        if day_locations.len() > 1 {
+            let coordinates = day_locations
+                .iter()
+                .map(|(location, _)| match location.altitude {
+                    Some(altitude) => {
+                        format!("{},{},{}", location.longitude, location.latitude, altitude)
+                    }
+                    None => format!("{},{}", location.longitude, location.latitude),
+                })
+                .collect::<Vec<String>>()
+                .join(" ");
+            folder.push_str(&format!(
+                "<Placemark><name>{}</name><styleUrl>#track</styleUrl><LineString><tessellate>1</tessellate><coordinates>{}</coordinates></LineString></Placemark>\n",
+                day, coordinates
+            ));
+        }
+
+        // the track allows playing back the day with the time slider of Google Earth
+        folder.push_str(&format!(
+            "<Placemark><name>{} (timeline)</name><styleUrl>#track</styleUrl><gx:Track>",
+            day
+        ));
+        for (location, _) in &day_locations {
+            folder.push_str(&format!(
+                "<when>{}</when>",
+                format_kml_time(location.measurement_time)
+            ));
+        }
+        for (location, _) in &day_locations {
+            folder.push_str(&format!(
+                "<gx:coord>{} {} {}</gx:coord>",
+                location.longitude,
+                location.latitude,
+                location.altitude.unwrap_or_default()
+            ));
+        }
+        folder.push_str("</gx:Track></Placemark>\n");
+
+        for (location, wifi_networks) in &day_locations {
+            if wifi_networks.is_empty() {
+                continue;
+            }
+            folder.push_str(&format!(
+                "<Placemark><name>{}</name><TimeStamp><when>{}</when></TimeStamp><styleUrl>#wifi</styleUrl><Point><coordinates>{},{}</coordinates></Point></Placemark>\n",
+                escape_xml(&wifi_networks.join(", ")),
+                format_kml_time(location.measurement_time),
+                location.longitude,
+                location.latitude
+            ));
+        }
+
+        folder.push_str("</Folder>\n");
+        Some(folder)
+    }
+}
+
+/// The columns which can be selected for a CSV export.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CsvColumn {
+    Id,
+    ReportingDevice,
+    Latitude,
+    Longitude,
+    Altitude,
+    HorizontalAccuracy,
+    VerticalAccuracy,
+    MeasurementTime,
+    MeasurementEpoch,
+    CreatedAt,
+    CreatedEpoch,
+    ReportTrigger,
+    BarometricPressure,
+    BatteryLevel,
+    BatteryStatus,
+    MonitoringMode,
+    Velocity,
+    CourseOverGround,
+    ConnectionType,
+    Topic,
+    InRegions,
+    WifiSsid,
+    WifiBssid,
+}
+
+impl CsvColumn {
+    const ALL: [CsvColumn; 23] = [
+        CsvColumn::Id,
+        CsvColumn::ReportingDevice,
+        CsvColumn::Latitude,
+        CsvColumn::Longitude,
+        CsvColumn::Altitude,
+        CsvColumn::HorizontalAccuracy,
+        CsvColumn::VerticalAccuracy,
+        CsvColumn::MeasurementTime,
+        CsvColumn::MeasurementEpoch,
+        CsvColumn::CreatedAt,
+        CsvColumn::CreatedEpoch,
+        CsvColumn::ReportTrigger,
+        CsvColumn::BarometricPressure,
+        CsvColumn::BatteryLevel,
+        CsvColumn::BatteryStatus,
+        CsvColumn::MonitoringMode,
+        CsvColumn::Velocity,
+        CsvColumn::CourseOverGround,
+        CsvColumn::ConnectionType,
+        CsvColumn::Topic,
+        CsvColumn::InRegions,
+        CsvColumn::WifiSsid,
+        CsvColumn::WifiBssid,
+    ];
+
+    fn get_name(&self) -> &'static str {
+        match self {
+            CsvColumn::Id => "id",
+            CsvColumn::ReportingDevice => "reporting_device",
+            CsvColumn::Latitude => "latitude",
+            CsvColumn::Longitude => "longitude",
+            CsvColumn::Altitude => "altitude",
+            CsvColumn::HorizontalAccuracy => "horizontal_accuracy",
+            CsvColumn::VerticalAccuracy => "vertical_accuracy",
+            CsvColumn::MeasurementTime => "measurement_time",
+            CsvColumn::MeasurementEpoch => "measurement_epoch",
+            CsvColumn::CreatedAt => "created_at",
+            CsvColumn::CreatedEpoch => "created_epoch",
+            CsvColumn::ReportTrigger => "report_trigger",
+            CsvColumn::BarometricPressure => "barometric_pressure",
+            CsvColumn::BatteryLevel => "battery_level",
+            CsvColumn::BatteryStatus => "battery_status",
+            CsvColumn::MonitoringMode => "monitoring_mode",
+            CsvColumn::Velocity => "velocity",
+            CsvColumn::CourseOverGround => "course_over_ground",
+            CsvColumn::ConnectionType => "connection_type",
+            CsvColumn::Topic => "topic",
+            CsvColumn::InRegions => "in_regions",
+            CsvColumn::WifiSsid => "wifi_ssid",
+            CsvColumn::WifiBssid => "wifi_bssid",
+        }
+    }
+
+    /// Get the columns of a comma-separated list of column names or all columns if no list was
+    /// supplied. Unknown column names are returned as error.
+    fn parse_list(column_names: Option<&str>) -> Result<Vec<CsvColumn>, String> {
+        let Some(column_names) = column_names else {
+            return Ok(CsvColumn::ALL.to_vec());
+        };
+
+        column_names
+            .split(',')
+            .map(|column_name| {
+                let column_name = column_name.trim();
+                CsvColumn::ALL
+                    .into_iter()
+                    .find(|column| column.get_name() == column_name)
+                    .ok_or_else(|| column_name.to_string())
+            })
+            .collect()
+    }
+}
+
+/// Escape a CSV field if it contains characters which would otherwise end the field or the row.
+fn escape_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Format a time as ISO 8601, either in UTC or in the supplied time zone.
+fn format_csv_time(time: NaiveDateTime, timezone: Option<Tz>) -> String {
+    match timezone {
+        Some(timezone) => time
+            .and_utc()
+            .with_timezone(&timezone)
+            .format("%Y-%m-%dT%H:%M:%S%:z")
+            .to_string(),
+        None => time.and_utc().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+    }
+}
+
+fn format_csv_row(
+    location: &Location,
+    access_points: &[WifiAccessPoint],
+    columns: &[CsvColumn],
+    timezone: Option<Tz>,
+) -> String {
+    fn optional<T: ToString>(value: &Option<T>) -> String {
+        value.as_ref().map(T::to_string).unwrap_or_default()
+    }
+
+    let fields: Vec<String> = columns
+        .iter()
+        .map(|column| match column {
+            CsvColumn::Id => location.id.to_string(),
+            CsvColumn::ReportingDevice => location.reporting_device.to_string(),
+            CsvColumn::Latitude => location.latitude.to_string(),
+            CsvColumn::Longitude => location.longitude.to_string(),
+            CsvColumn::Altitude => optional(&location.altitude),
+            CsvColumn::HorizontalAccuracy => optional(&location.horizontal_accuracy),
+            CsvColumn::VerticalAccuracy => optional(&location.vertical_accuracy),
+            CsvColumn::MeasurementTime => format_csv_time(location.measurement_time, timezone),
+            CsvColumn::MeasurementEpoch => {
+                location.measurement_time.and_utc().timestamp().to_string()
+            }
+            CsvColumn::CreatedAt => location
+                .created_at
+                .map(|created_at| format_csv_time(created_at, timezone))
+                .unwrap_or_default(),
+            CsvColumn::CreatedEpoch => location
+                .created_at
+                .map(|created_at| created_at.and_utc().timestamp().to_string())
+                .unwrap_or_default(),
+            CsvColumn::ReportTrigger => location.report_trigger.clone(),
+            CsvColumn::BarometricPressure => optional(&location.barometric_pressure),
+            CsvColumn::BatteryLevel => optional(&location.battery_level),
+            CsvColumn::BatteryStatus => optional(&location.battery_status),
+            CsvColumn::MonitoringMode => optional(&location.monitoring_mode),
+            CsvColumn::Velocity => optional(&location.velocity),
+            CsvColumn::CourseOverGround => optional(&location.course_over_ground),
+            CsvColumn::ConnectionType => optional(&location.connection_type),
+            CsvColumn::Topic => optional(&location.topic),
+            CsvColumn::InRegions => location
+                .in_regions
+                .iter()
+                .flatten()
+                .flatten()
+                .cloned()
+                .collect::<Vec<String>>()
+                .join(";"),
+            // a location is only part of one row, even if the device saw multiple access points
+            CsvColumn::WifiSsid => access_points
+                .iter()
+                .map(|access_point| access_point.ssid.clone())
+                .collect::<Vec<String>>()
+                .join(";"),
+            CsvColumn::WifiBssid => access_points
+                .iter()
+                .map(|access_point| access_point.bssid.clone())
+                .collect::<Vec<String>>()
+                .join(";"),
+        })
+        .map(|field| escape_csv(&field))
+        .collect();
+
+    format!("{}\n", fields.join(","))
+}
+
+#[options("/devices/<_device_id>/exports/gpx")]
+pub fn get_gpx_export_options(_device_id: i32) -> Status {
+    Status::Ok
+}
+
+#[get("/devices/<device_id>/exports/gpx?<from>&<to>")]
+pub fn get_gpx_export(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<ExportResponse<TextStream![String]>, Status> {
+    let from = parse_timestamp(from)?;
+    let to = parse_timestamp(to)?;
+
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+
+    let mut batches = LocationBatches::new(db_connection_pool.inner().clone(), device_id, from, to);
+    let gpx_document = TextStream! {
+        yield format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<gpx version=\"1.1\" creator=\"thereiwas\" xmlns=\"http://www.topografix.com/GPX/1/1\" xmlns:thereiwas=\"{}\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" xsi:schemaLocation=\"http://www.topografix.com/GPX/1/1 http://www.topografix.com/GPX/1/1/gpx.xsd\">\n",
+            GPX_EXTENSION_NAMESPACE
+        );
+
+        // every day gets its own track
+        let mut current_day: Option<NaiveDate> = None;
+        loop {
+            let batch = match batches.next_batch().await {
+                Ok(Some(batch)) => batch,
+                Ok(None) => break,
+                // the closing elements are left out, so the truncated document is not valid
+                Err(_) => {
+                    yield INCOMPLETE_XML_EXPORT_MARKER.to_string();
+                    return;
+                }
+            };
+            for location in batch {
+                let day = location.measurement_time.date();
+                if current_day != Some(day) {
+                    if current_day.is_some() {
+                        yield "</trkseg></trk>\n".to_string();
+                    }
+                    yield format!("<trk><name>{}</name><trkseg>\n", day.format("%Y-%m-%d"));
+                    current_day = Some(day);
+                }
+                yield format_gpx_track_point(&location);
+            }
+        }
+        if current_day.is_some() {
+            yield "</trkseg></trk>\n".to_string();
+        }
+
+        yield "</gpx>\n".to_string();
+    };
+
+    Ok(ExportResponse::new(
+        gpx_document,
+        ContentType::new("application", "gpx+xml"),
+        format!("thereiwas-device-{}.gpx", device_id),
+    ))
+}
+
+#[options("/devices/<_device_id>/exports/kml")]
+pub fn get_kml_export_options(_device_id: i32) -> Status {
+    Status::Ok
+}
+
+#[get("/devices/<device_id>/exports/kml?<from>&<to>")]
+pub fn get_kml_export(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<ExportResponse<TextStream![String]>, Status> {
+    let from = parse_timestamp(from)?;
+    let to = parse_timestamp(to)?;
+
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+
+    let mut batches = LocationBatches::new(db_connection_pool.inner().clone(), device_id, from, to);
+    let kml_document = TextStream! {
+        yield KmlDocument::get_header(device_id);
+
+        let mut document = KmlDocument::new();
+        loop {
+            let batch = match batches.next_batch().await {
+                Ok(Some(batch)) => batch,
+                Ok(None) => break,
+                // the closing elements are left out, so the truncated document is not valid
+                Err(_) => {
+                    yield INCOMPLETE_XML_EXPORT_MARKER.to_string();
+                    return;
+                }
+            };
+            let Ok(mut access_points) = batches.get_wifi_access_points(&batch).await else {
+                yield INCOMPLETE_XML_EXPORT_MARKER.to_string();
+                return;
+            };
+            for location in batch {
+                let wifi_networks = get_wifi_network_names(access_points.remove(&location.id));
+                if let Some(finished_day) = document.push(location, wifi_networks) {
+                    yield finished_day;
+                }
+            }
+        }
+
+        yield document.finish();
+    };
+
+    Ok(ExportResponse::new(
+        kml_document,
+        ContentType::new("application", "vnd.google-earth.kml+xml"),
+        format!("thereiwas-device-{}.kml", device_id),
+    ))
+}
+
+#[options("/devices/<_device_id>/exports/kmz")]
+pub fn get_kmz_export_options(_device_id: i32) -> Status {
+    Status::Ok
+}
+
+/// The KMZ export contains the same document as the KML export. Since a ZIP archive can not be
+/// streamed, only the compressed document is kept in memory.
+#[get("/devices/<device_id>/exports/kmz?<from>&<to>")]
+pub async fn get_kmz_export(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<ExportResponse<Vec<u8>>, Status> {
+    let from = parse_timestamp(from)?;
+    let to = parse_timestamp(to)?;
+
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+    drop(db_connection);
+
+    // the days are compressed on the blocking pool while the next locations are loaded
+    let (day_sender, mut day_receiver) = mpsc::channel::<String>(KMZ_PENDING_DAYS);
+    let compression = rocket::tokio::task::spawn_blocking(move || {
+        let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+        archive.start_file(
+            "doc.kml",
+            SimpleFileOptions::default().compression_method(CompressionMethod::Deflated),
+        )?;
+        archive.write_all(KmlDocument::get_header(device_id).as_bytes())?;
+        while let Some(day) = day_receiver.blocking_recv() {
+            archive.write_all(day.as_bytes())?;
+        }
+        Ok::<_, std::io::Error>(archive.finish()?.into_inner())
+    });
+
+    let mut batches = LocationBatches::new(db_connection_pool.inner().clone(), device_id, from, to);
+    let mut document = KmlDocument::new();
+    'batches: while let Some(batch) = batches.next_batch().await? {
+        let mut access_points = batches.get_wifi_access_points(&batch).await?;
+        for location in batch {
+            let wifi_networks = get_wifi_network_names(access_points.remove(&location.id));
+            if let Some(finished_day) = document.push(location, wifi_networks) {
+                // the receiver is only gone if the compression failed, which is reported below
+                if day_sender.send(finished_day).await.is_err() {
+                    break 'batches;
+                }
+            }
+        }
+    }
+    // the result of sending the last day is not needed for the same reason
+    let _ = day_sender.send(document.finish()).await;
+    drop(day_sender);
+
+    let compressed_document = compression
+        .await
+        .map_err(|error| error.to_string())
+        .and_then(|compression_result| compression_result.map_err(|error| error.to_string()))
+        .map_err(|error| {
+            error!(
+                "Could not compress the KML document of the device {}. The error was: {}",
+                device_id, error
+            );
+            Status::InternalServerError
+        })?;
+
+    Ok(ExportResponse::new(
+        compressed_document,
+        ContentType::new("application", "vnd.google-earth.kmz"),
+        format!("thereiwas-device-{}.kmz", device_id),
+    ))
+}
+
+#[options("/devices/<_device_id>/exports/csv")]
+pub fn get_csv_export_options(_device_id: i32) -> Status {
+    Status::Ok
+}
+
+#[get("/devices/<device_id>/exports/csv?<from>&<to>&<columns>&<timezone>")]
+pub fn get_csv_export(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+    from: Option<i64>,
+    to: Option<i64>,
+    columns: Option<&str>,
+    timezone: Option<&str>,
+) -> Result<ExportResponse<TextStream![String]>, Status> {
+    let from = parse_timestamp(from)?;
+    let to = parse_timestamp(to)?;
+    let columns = CsvColumn::parse_list(columns).map_err(|column_name| {
+        error!(
+            "Could not export the locations as CSV since the column '{}' is unknown",
+            column_name
+        );
+        Status::BadRequest
+    })?;
+    let timezone = timezone
+        .map(|timezone| timezone.parse::<Tz>())
+        .transpose()
+        .map_err(|error| {
+            error!(
+                "Could not export the locations as CSV since the time zone is invalid. The error was: {}",
+                error
+            );
+            Status::BadRequest
+        })?;
+
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+
+    let mut batches = LocationBatches::new(db_connection_pool.inner().clone(), device_id, from, to);
+    let csv_document = TextStream! {
+        let header: Vec<&str> = columns.iter().map(CsvColumn::get_name).collect();
+        yield format!("{}\n", header.join(","));
+
+        let includes_wifi_access_points = columns
+            .iter()
+            .any(|column| matches!(column, CsvColumn::WifiSsid | CsvColumn::WifiBssid));
+        loop {
+            let batch = match batches.next_batch().await {
+                Ok(Some(batch)) => batch,
+                Ok(None) => break,
+                Err(_) => {
+                    yield INCOMPLETE_CSV_EXPORT_MARKER.to_string();
+                    return;
+                }
+            };
+            let access_points = if includes_wifi_access_points {
+                match batches.get_wifi_access_points(&batch).await {
+                    Ok(access_points) => access_points,
+                    Err(_) => {
+                        yield INCOMPLETE_CSV_EXPORT_MARKER.to_string();
+                        return;
+                    }
+                }
+            } else {
+                HashMap::new()
+            };
+
+            let mut rows = String::new();
+            for location in &batch {
+                let location_access_points = access_points
+                    .get(&location.id)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                rows.push_str(&format_csv_row(location, location_access_points, &columns, timezone));
+            }
+            yield rows;
+        }
+    };
+
+    Ok(ExportResponse::new(
+        csv_document,
+        ContentType::CSV,
+        format!("thereiwas-device-{}.csv", device_id),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::get_test_location;
+
+    #[test]
+    fn test_gpx_track_points_contain_the_location_details() {
+        assert_eq!(
+            format_gpx_track_point(&get_test_location(1, 1, 1_700_000_000)),
+            "<trkpt lat=\"51.2353\" lon=\"6.7829\"><ele>38</ele><time>2023-11-14T22:13:20Z</time><extensions><thereiwas:horizontal_accuracy>12</thereiwas:horizontal_accuracy><thereiwas:barometric_pressure>101.3</thereiwas:barometric_pressure><thereiwas:report_trigger>p</thereiwas:report_trigger></extensions></trkpt>\n"
+        );
+        assert_eq!(escape_xml("a<b & \"c\""), "a&lt;b &amp; &quot;c&quot;");
+    }
+
+    #[test]
+    fn test_kml_days_contain_a_line_a_track_and_the_wifi_placemarks() {
+        let mut document = KmlDocument::new();
+        let mut second_location = get_test_location(1, 1, 1_700_000_000);
+        second_location.id = 2;
+        second_location.altitude = None;
+        second_location.measurement_time += chrono::Duration::minutes(5);
+        let mut next_day_location = get_test_location(1, 1, 1_700_000_000);
+        next_day_location.measurement_time += chrono::Duration::days(1);
+
+        assert!(document
+            .push(get_test_location(1, 1, 1_700_000_000), vec![])
+            .is_none());
+        assert!(document
+            .push(second_location, vec!["Home & Garden".to_string()])
+            .is_none());
+        let first_day = document.push(next_day_location, vec![]).unwrap();
+
+        assert!(first_day.contains("<coordinates>6.7829,51.2353,38 6.7829,51.2353</coordinates>"));
+        assert!(first_day.contains("<when>2023-11-14T22:13:20Z</when><when>2023-11-14T22:18:20Z</when><gx:coord>6.7829 51.2353 38</gx:coord><gx:coord>6.7829 51.2353 0</gx:coord>"));
+        assert!(first_day.contains(
+            "<name>Home &amp; Garden</name><TimeStamp><when>2023-11-14T22:18:20Z</when>"
+        ));
+
+        // a single location can not be a line, but it is still part of the track
+        let last_day = document.finish();
+        assert!(!last_day.contains("<LineString>"));
+        assert!(last_day.contains("<Folder><name>2023-11-15</name>"));
+        assert!(last_day.ends_with("</Document></kml>\n"));
+    }
+
+    #[test]
+    fn test_hidden_wifi_networks_are_named_by_their_bssid() {
+        let access_points = vec![
+            WifiAccessPoint {
+                id: 1,
+                bssid: "AA:BB:CC:DD:EE:FF".to_string(),
+                ssid: "".to_string(),
+                last_seen: None,
+            },
+            WifiAccessPoint {
+                id: 2,
+                bssid: "AA:BB:CC:DD:EE:00".to_string(),
+                ssid: "Guests".to_string(),
+                last_seen: None,
+            },
+        ];
+
+        assert_eq!(
+            get_wifi_network_names(Some(access_points)),
+            vec!["AA:BB:CC:DD:EE:FF".to_string(), "Guests".to_string()]
+        );
+        assert!(get_wifi_network_names(None).is_empty());
+    }
+
+    #[test]
+    fn test_csv_rows_contain_the_selected_columns() {
+        let columns = CsvColumn::parse_list(Some(
+            "id,measurement_time,measurement_epoch,wifi_ssid,velocity",
+        ))
+        .unwrap();
+        let access_points = [
+            WifiAccessPoint {
+                id: 1,
+                bssid: "AA:BB:CC:DD:EE:FF".to_string(),
+                ssid: "Home, 5G".to_string(),
+                last_seen: None,
+            },
+            WifiAccessPoint {
+                id: 2,
+                bssid: "AA:BB:CC:DD:EE:00".to_string(),
+                ssid: "Guests".to_string(),
+                last_seen: None,
+            },
+        ];
+
+        assert_eq!(
+            format_csv_row(
+                &get_test_location(1, 1, 1_700_000_000),
+                &access_points,
+                &columns,
+                None
+            ),
+            "1,2023-11-14T22:13:20Z,1700000000,\"Home, 5G;Guests\",\n"
+        );
+        assert_eq!(
+            format_csv_row(
+                &get_test_location(1, 1, 1_700_000_000),
+                &[],
+                &columns,
+                "Europe/Berlin".parse().ok()
+            ),
+            "1,2023-11-14T23:13:20+01:00,1700000000,,\n"
+        );
+        assert_eq!(CsvColumn::parse_list(None).unwrap().len(), 23);
+        assert_eq!(
+            CsvColumn::parse_list(Some("id,speed")),
+            Err("speed".to_string())
+        );
+        assert_eq!(escape_csv("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
@@ -0,0 +1,400 @@
+use crate::fairings::ThereIWasDatabaseConnection;
+use crate::guards::AuthenticatedUser;
+use crate::models::{DeviceCard, Location, LocationShare, NewDeviceCard, NewLocationShare};
+use crate::routes::commands::{CardMessage, OwnTracksMessage};
+use crate::schema::device_cards::dsl::device_cards;
+use crate::schema::location_shares::dsl::location_shares;
+use crate::schema::location_shares::{sharing_device, viewing_device};
+use diesel::result::DatabaseErrorKind;
+use diesel::result::Error::DatabaseError;
+use diesel::{
+    BoolExpressionMethods, ExpressionMethods, JoinOnDsl, NullableExpressionMethods, PgConnection,
+    QueryDsl, RunQueryDsl,
+};
+use log::error;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{delete, get, options, post, put, State};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+/// The maximal number of characters of the name on a card (see the `device_cards` table).
+pub(crate) const MAXIMUM_CARD_NAME_LENGTH: usize = 128;
+
+/// The location of a friend in the format the OwnTracks apps expect it.
+#[derive(Serialize)]
+struct FriendLocationMessage {
+    #[serde(rename = "_type")]
+    message_type: &'static str,
+    lat: f64,
+    lon: f64,
+    tst: i64,
+    tid: String,
+    topic: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    acc: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alt: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vac: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    batt: Option<i16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vel: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cog: Option<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct CardInformation {
+    /// The name which should be shown to friends.
+    name: String,
+    /// A base64 encoded PNG image which should be shown to friends.
+    face: Option<String>,
+    /// The two character tracker id which should be shown to friends.
+    tracker_id: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct CardRecord {
+    pub name: String,
+    pub face: Option<String>,
+    pub tracker_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ShareInformation {
+    /// The device which should be able to see the location of the sharing device.
+    device: i32,
+}
+
+#[derive(Serialize)]
+pub struct ShareRecord {
+    pub device: i32,
+}
+
+/// The topic which is used to identify a friend in the OwnTracks apps.
+fn get_topic_for_device(device_id: i32) -> String {
+    format!("owntracks/thereiwas/{}", device_id)
+}
+
+/// The tracker id which is used if the device did not configure one on its card. OwnTracks
+/// itself uses the last two characters of the device id in this case.
+fn get_default_tracker_id(device_id: i32) -> String {
+    let formatted_device_id = format!("{:02}", device_id);
+    formatted_device_id[formatted_device_id.len() - 2..].to_string()
+}
+
+/// The errors which can occur while the messages about the friends of a device are collected.
+#[derive(Debug)]
+pub enum FriendMessagesError {
+    /// The shared devices, their cards or their locations could not be queried
+    DatabaseError(diesel::result::Error),
+    /// A message could not be converted into its JSON representation
+    SerializationError(serde_json::Error),
+}
+
+impl Display for FriendMessagesError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FriendMessagesError::DatabaseError(error) => write!(f, "{}", error),
+            FriendMessagesError::SerializationError(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<diesel::result::Error> for FriendMessagesError {
+    fn from(error: diesel::result::Error) -> Self {
+        FriendMessagesError::DatabaseError(error)
+    }
+}
+
+/// Convert a message for the OwnTracks apps into its JSON representation.
+fn to_message_value<T: Serialize>(message: T) -> Result<Value, FriendMessagesError> {
+    serde_json::to_value(message).map_err(FriendMessagesError::SerializationError)
+}
+
+/// Get the last known location and the card of all devices which are shared with the supplied
+/// device in the format the OwnTracks apps expect them. Revoked devices are not shared anymore.
+pub fn get_friend_messages(
+    device_id: i32,
+    db_connection: &mut PgConnection,
+) -> Result<Vec<Value>, FriendMessagesError> {
+    use crate::schema::client_tokens::dsl::client_tokens;
+    use crate::schema::client_tokens::{id as client_token_id, revoked_at};
+    use crate::schema::device_cards::reporting_device as card_reporting_device;
+    use crate::schema::locations::dsl::locations;
+    use crate::schema::locations::{measurement_time, reporting_device};
+
+    let friends = location_shares
+        .inner_join(client_tokens.on(client_token_id.eq(sharing_device)))
+        .left_join(device_cards.on(card_reporting_device.eq(sharing_device)))
+        .filter(viewing_device.eq(device_id))
+        .filter(revoked_at.is_null())
+        .order_by(sharing_device.asc())
+        .select((
+            sharing_device,
+            crate::schema::device_cards::all_columns.nullable(),
+        ))
+        .load::<(i32, Option<DeviceCard>)>(db_connection)?;
+
+    // the newest location of each friend
+    let mut last_locations = locations
+        .filter(reporting_device.eq_any(friends.iter().map(|(friend_id, _)| *friend_id)))
+        .distinct_on(reporting_device)
+        .order_by((reporting_device, measurement_time.desc()))
+        .load::<Location>(db_connection)?
+        .into_iter()
+        .map(|location| (location.reporting_device, location))
+        .collect::<HashMap<_, _>>();
+
+    let mut messages = vec![];
+    for (friend_id, card) in friends {
+        let topic = get_topic_for_device(friend_id);
+        let tracker_id = card
+            .as_ref()
+            .and_then(|card| card.tracker_id.clone())
+            .unwrap_or(get_default_tracker_id(friend_id));
+
+        if let Some(location) = last_locations.remove(&friend_id) {
+            messages.push(to_message_value(FriendLocationMessage {
+                message_type: "location",
+                lat: location.latitude,
+                lon: location.longitude,
+                tst: location.measurement_time.and_utc().timestamp(),
+                tid: tracker_id.clone(),
+                topic: topic.clone(),
+                acc: location.horizontal_accuracy,
+                alt: location.altitude,
+                vac: location.vertical_accuracy,
+                batt: location.battery_level,
+                vel: location.velocity,
+                cog: location.course_over_ground,
+            })?);
+        }
+        if let Some(card) = card {
+            messages.push(to_message_value(OwnTracksMessage::Card(CardMessage {
+                name: card.name,
+                face: card.face,
+                tid: Some(tracker_id),
+                topic: Some(topic),
+            }))?);
+        }
+    }
+
+    Ok(messages)
+}
+
+#[options("/devices/<_device_id>/card")]
+pub fn get_device_card_options(_device_id: i32) -> Status {
+    Status::Ok
+}
+
+#[options("/devices/<_device_id>/shares")]
+pub fn get_device_shares_options(_device_id: i32) -> Status {
+    Status::Ok
+}
+
+#[options("/devices/<_device_id>/shares/<_viewer>")]
+pub fn get_device_share_options(_device_id: i32, _viewer: i32) -> Status {
+    Status::Ok
+}
+
+#[get("/devices/<device_id>/card")]
+pub fn get_device_card(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+) -> Result<Json<CardRecord>, Status> {
+    use crate::schema::device_cards::reporting_device;
+
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+
+    device_cards
+        .filter(reporting_device.eq(device_id))
+        .first::<DeviceCard>(&mut db_connection)
+        .map(|card| {
+            Json(CardRecord {
+                name: card.name,
+                face: card.face,
+                tracker_id: card.tracker_id,
+            })
+        })
+        .map_err(|error| match error {
+            diesel::result::Error::NotFound => Status::NotFound,
+            _ => {
+                error!(
+                    "Could not query the card of the device {}. The error was: {}",
+                    device_id, error
+                );
+                Status::InternalServerError
+            }
+        })
+}
+
+#[put("/devices/<device_id>/card", data = "<card_information>")]
+pub fn set_device_card(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+    card_information: Json<CardInformation>,
+) -> Result<Status, Status> {
+    use crate::schema::device_cards::reporting_device;
+
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+    if card_information.name.is_empty()
+        || card_information.name.chars().count() > MAXIMUM_CARD_NAME_LENGTH
+        || card_information
+            .tracker_id
+            .as_ref()
+            .is_some_and(|tracker_id| tracker_id.chars().count() > 2)
+    {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let card_information = card_information.into_inner();
+    let new_card = NewDeviceCard {
+        reporting_device: device_id,
+        tracker_id: card_information.tracker_id,
+        name: card_information.name,
+        face: card_information.face,
+    };
+
+    diesel::insert_into(crate::schema::device_cards::table)
+        .values(&new_card)
+        .on_conflict(reporting_device)
+        .do_update()
+        .set(&new_card)
+        .execute(&mut db_connection)
+        .map(|_| Status::NoContent)
+        .map_err(|error| match error {
+            // users who can access all devices pass the access check even for unknown devices
+            DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => Status::NotFound,
+            _ => {
+                error!(
+                    "Could not store the card of the device {}. The error was: {}",
+                    device_id, error
+                );
+                Status::InternalServerError
+            }
+        })
+}
+
+#[get("/devices/<device_id>/shares")]
+pub fn get_device_shares(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+) -> Result<Json<Vec<ShareRecord>>, Status> {
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+
+    location_shares
+        .filter(sharing_device.eq(device_id))
+        .order_by(viewing_device.asc())
+        .load::<LocationShare>(&mut db_connection)
+        .map(|shares| {
+            Json(
+                shares
+                    .into_iter()
+                    .map(|share| ShareRecord {
+                        device: share.viewing_device,
+                    })
+                    .collect(),
+            )
+        })
+        .map_err(|error| {
+            error!(
+                "Could not query the shares of the device {}. The error was: {}",
+                device_id, error
+            );
+            Status::InternalServerError
+        })
+}
+
+#[post("/devices/<device_id>/shares", data = "<share_information>")]
+pub fn add_device_share(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+    share_information: Json<ShareInformation>,
+) -> Result<Status, Status> {
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+    if share_information.device == device_id {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    match diesel::insert_into(crate::schema::location_shares::table)
+        .values(&NewLocationShare {
+            sharing_device: device_id,
+            viewing_device: share_information.device,
+        })
+        .execute(&mut db_connection)
+    {
+        Ok(_) => Ok(Status::NoContent),
+        Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Err(Status::Conflict),
+        Err(DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => Err(Status::NotFound),
+        Err(error) => {
+            error!(
+                "Could not share the location of the device {} with the device {}. The error was: {}",
+                device_id, share_information.device, error
+            );
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[delete("/devices/<device_id>/shares/<viewer>")]
+pub fn delete_device_share(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+    viewer: i32,
+) -> Result<Status, Status> {
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+
+    match diesel::delete(
+        location_shares.filter(sharing_device.eq(device_id).and(viewing_device.eq(viewer))),
+    )
+    .execute(&mut db_connection)
+    {
+        Ok(0) => Err(Status::NotFound),
+        Ok(_) => Ok(Status::NoContent),
+        Err(error) => {
+            error!(
+                "Could not stop sharing the location of the device {} with the device {}. The error was: {}",
+                device_id, viewer, error
+            );
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_tracker_id_uses_the_last_two_digits() {
+        assert_eq!(get_default_tracker_id(7), "07");
+        assert_eq!(get_default_tracker_id(42), "42");
+        assert_eq!(get_default_tracker_id(1234), "34");
+    }
+}
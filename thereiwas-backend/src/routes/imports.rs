@@ -0,0 +1,298 @@
+use crate::fairings::ThereIWasDatabaseConnection;
+use crate::guards::AuthenticatedUser;
+use crate::imports::takeout::parse_takeout_file;
+use crate::imports::{parse_track_file, ParsedImport};
+use crate::models::{Location, NewLocation, NewLocationToWifiAccessPoint, NewWifiAccessPoint};
+use crate::schema::client_tokens::dsl::client_tokens;
+use crate::schema::locations::dsl::locations;
+use crate::schema::locations_to_wifi_access_points::dsl::locations_to_wifi_access_points;
+use crate::schema::wifi_access_points::dsl::wifi_access_points;
+use crate::schema::wifi_access_points::{bssid, id as wifi_access_point_id, ssid};
+use crate::{log_audit_message, AuditLogAction, AuditLogResult};
+use chrono::NaiveDateTime;
+use diesel::dsl::exists;
+use diesel::upsert::on_constraint;
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension, PgConnection,
+    QueryDsl, RunQueryDsl,
+};
+use log::{error, info};
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{options, post, State};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// The maximal size of a file which can be imported. The whole file is buffered and parsed in
+/// memory, which takes several times its size, so larger location histories have to be split up
+/// before they are imported.
+const MAXIMUM_IMPORT_SIZE_IN_MEBIBYTES: u64 = 128;
+
+/// The number of locations which are stored with one query.
+const IMPORT_BATCH_SIZE: usize = 1000;
+
+#[derive(Serialize)]
+pub struct ImportSummaryRecord {
+    /// The number of locations which were stored.
+    pub inserted_locations: usize,
+    /// The number of locations which were already stored before.
+    pub duplicate_locations: usize,
+    /// The number of entries of the file which did not contain a valid location.
+    pub rejected_locations: usize,
+}
+
+/// Get the id of the WiFi access point with the supplied BSSID or store it, if it is not known
+/// yet. The imported location histories do not contain the SSID of the access points.
+fn get_wifi_access_point_id(
+    access_point_bssid: &str,
+    seen_at: NaiveDateTime,
+    db_connection: &mut PgConnection,
+) -> Result<i32, diesel::result::Error> {
+    let stored_id = diesel::insert_into(wifi_access_points)
+        .values(&NewWifiAccessPoint {
+            bssid: access_point_bssid.to_string(),
+            ssid: String::new(),
+            last_seen: Some(seen_at),
+        })
+        .on_conflict_do_nothing()
+        .returning(wifi_access_point_id)
+        .get_result::<i32>(db_connection)
+        .optional()?;
+    match stored_id {
+        Some(stored_id) => Ok(stored_id),
+        None => wifi_access_points
+            .filter(bssid.eq(access_point_bssid).and(ssid.eq("")))
+            .select(wifi_access_point_id)
+            .first::<i32>(db_connection),
+    }
+}
+
+/// Store the imported locations for the supplied device. Locations which were already stored
+/// (see `locations_unique_key`) are skipped. All locations are stored in one transaction, so a
+/// failed import can just be repeated.
+fn store_imported_locations(
+    device_id: i32,
+    parsed_import: ParsedImport,
+    db_connection: &mut PgConnection,
+) -> Result<ImportSummaryRecord, diesel::result::Error> {
+    let imported_locations = parsed_import.locations.len();
+    let mut inserted_locations = 0;
+    let mut known_access_points = HashMap::<String, i32>::new();
+
+    db_connection.transaction::<_, diesel::result::Error, _>(|db_connection| {
+        let mut batch_iterator = parsed_import.locations.into_iter().peekable();
+        while batch_iterator.peek().is_some() {
+            let mut access_points_of_batch = HashMap::new();
+            let new_locations: Vec<NewLocation> = batch_iterator
+                .by_ref()
+                .take(IMPORT_BATCH_SIZE)
+                .map(|imported_location| {
+                    if let Some(access_point_bssid) = &imported_location.wifi_bssid {
+                        access_points_of_batch.insert(
+                            (
+                                imported_location.measurement_time,
+                                imported_location.latitude.to_bits(),
+                                imported_location.longitude.to_bits(),
+                            ),
+                            access_point_bssid.clone(),
+                        );
+                    }
+                    imported_location.into_new_location(device_id)
+                })
+                .collect();
+
+            let stored_locations = diesel::insert_into(locations)
+                .values(&new_locations)
+                .on_conflict(on_constraint("locations_unique_key"))
+                .do_nothing()
+                .get_results::<Location>(db_connection)?;
+            inserted_locations += stored_locations.len();
+
+            let mut new_associations = vec![];
+            for stored_location in stored_locations {
+                let Some(access_point_bssid) = access_points_of_batch.get(&(
+                    stored_location.measurement_time,
+                    stored_location.latitude.to_bits(),
+                    stored_location.longitude.to_bits(),
+                )) else {
+                    continue;
+                };
+                let access_point_id = match known_access_points.get(access_point_bssid) {
+                    Some(access_point_id) => *access_point_id,
+                    None => {
+                        let access_point_id = get_wifi_access_point_id(
+                            access_point_bssid,
+                            stored_location.measurement_time,
+                            db_connection,
+                        )?;
+                        known_access_points.insert(access_point_bssid.clone(), access_point_id);
+                        access_point_id
+                    }
+                };
+                new_associations.push(NewLocationToWifiAccessPoint {
+                    location_id: stored_location.id,
+                    wifi_access_point_id: access_point_id,
+                });
+            }
+            if !new_associations.is_empty() {
+                diesel::insert_into(locations_to_wifi_access_points)
+                    .values(&new_associations)
+                    .execute(db_connection)?;
+            }
+        }
+        Ok(())
+    })?;
+
+    Ok(ImportSummaryRecord {
+        inserted_locations,
+        duplicate_locations: imported_locations - inserted_locations,
+        rejected_locations: parsed_import.rejected_locations,
+    })
+}
+
+/// Read the uploaded file, parse it with the supplied parser and store the locations it contains
+/// for the supplied device.
+async fn import_file(
+    db_connection_pool: &ThereIWasDatabaseConnection,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+    uploaded_file: Data<'_>,
+    client_ip: Option<IpAddr>,
+    parse_file: fn(&[u8]) -> Result<ParsedImport, String>,
+) -> Result<Json<ImportSummaryRecord>, Status> {
+    let remote_endppoint = client_ip.unwrap_or(IpAddr::from([0, 0, 0, 0])).to_string();
+
+    // the connection is not kept while the (possibly large) file is uploaded
+    let db_connection_pool = db_connection_pool.clone();
+    let username = authenticated_user.username.clone();
+    let access_check_pool = db_connection_pool.clone();
+    rocket::tokio::task::spawn_blocking(move || {
+        let mut db_connection = access_check_pool
+            .get()
+            .map_err(|_| Status::ServiceUnavailable)?;
+        authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+        // users who can access all devices pass the check above even for devices which do not
+        // exist
+        let is_registered_device = diesel::select(exists(client_tokens.find(device_id)))
+            .get_result::<bool>(&mut db_connection)
+            .map_err(|error| {
+                error!(
+                    "Could not check if the device {} exists. The error was: {}",
+                    device_id, error
+                );
+                Status::InternalServerError
+            })?;
+        if !is_registered_device {
+            return Err(Status::NotFound);
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|_| Status::InternalServerError)??;
+
+    let uploaded_file = uploaded_file
+        .open(MAXIMUM_IMPORT_SIZE_IN_MEBIBYTES.mebibytes())
+        .into_bytes()
+        .await
+        .map_err(|error| {
+            error!(
+                "Could not read the file which should be imported. The error was: {}",
+                error
+            );
+            Status::BadRequest
+        })?;
+    if !uploaded_file.is_complete() {
+        return Err(Status::PayloadTooLarge);
+    }
+
+    // parsing and storing a location history of many years takes a while
+    rocket::tokio::task::spawn_blocking(move || {
+        let parsed_import = parse_file(&uploaded_file).map_err(|error| {
+            error!(
+                "Could not parse the file which should be imported. The error was: {}",
+                error
+            );
+            Status::UnprocessableEntity
+        })?;
+
+        let mut db_connection = db_connection_pool
+            .get()
+            .map_err(|_| Status::ServiceUnavailable)?;
+        let import_summary = store_imported_locations(device_id, parsed_import, &mut db_connection)
+            .map_err(|error| {
+                error!(
+                    "Could not store the imported locations of the device {}. The error was: {}",
+                    device_id, error
+                );
+                Status::InternalServerError
+            })?;
+
+        info!(
+            "{} locations were imported for the device {} by '{}'",
+            import_summary.inserted_locations, device_id, username
+        );
+        log_audit_message(
+            &mut db_connection,
+            AuditLogAction::LocationImport,
+            AuditLogResult::Successful,
+            &remote_endppoint,
+        );
+
+        Ok(Json(import_summary))
+    })
+    .await
+    .map_err(|_| Status::InternalServerError)?
+}
+
+#[options("/devices/<_device_id>/imports/takeout")]
+pub fn import_takeout_options(_device_id: i32) -> Status {
+    Status::Ok
+}
+
+/// Import a `Records.json` (Google Takeout) or `Timeline.json` (exported on the device) file as
+/// locations of the supplied device.
+#[post("/devices/<device_id>/imports/takeout", data = "<takeout_file>")]
+pub async fn import_takeout(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+    takeout_file: Data<'_>,
+    client_ip: Option<IpAddr>,
+) -> Result<Json<ImportSummaryRecord>, Status> {
+    import_file(
+        db_connection_pool,
+        authenticated_user,
+        device_id,
+        takeout_file,
+        client_ip,
+        parse_takeout_file,
+    )
+    .await
+}
+
+#[options("/devices/<_device_id>/imports/tracks")]
+pub fn import_track_options(_device_id: i32) -> Status {
+    Status::Ok
+}
+
+/// Import a GPX, KML or GeoJSON file as locations of the supplied device.
+#[post("/devices/<device_id>/imports/tracks", data = "<track_file>")]
+pub async fn import_track(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+    track_file: Data<'_>,
+    client_ip: Option<IpAddr>,
+) -> Result<Json<ImportSummaryRecord>, Status> {
+    import_file(
+        db_connection_pool,
+        authenticated_user,
+        device_id,
+        track_file,
+        client_ip,
+        parse_track_file,
+    )
+    .await
+}
@@ -0,0 +1,64 @@
+use crate::fairings::ThereIWasDatabaseConnection;
+use crate::guards::AuthenticatedUser;
+use crate::models::LastWill;
+use crate::schema::last_wills::dsl::last_wills;
+use crate::schema::last_wills::{received_at, reporting_device};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use log::error;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{get, options, State};
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct LastWillRecord {
+    pub connected_at: i64,
+    pub received_at: i64,
+}
+
+impl From<LastWill> for LastWillRecord {
+    fn from(last_will: LastWill) -> Self {
+        LastWillRecord {
+            connected_at: last_will.connected_at.and_utc().timestamp(),
+            received_at: last_will.received_at.and_utc().timestamp(),
+        }
+    }
+}
+
+#[options("/devices/<_device_id>/last_wills")]
+pub fn get_last_wills_options(_device_id: i32) -> Status {
+    Status::Ok
+}
+
+#[get("/devices/<device_id>/last_wills?<limit>")]
+pub fn get_last_wills(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+    limit: Option<i64>,
+) -> Result<Json<Vec<LastWillRecord>>, Status> {
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+
+    let received_last_wills = last_wills
+        .filter(reporting_device.eq(device_id))
+        .order_by(received_at.desc())
+        .limit(limit.unwrap_or(100).clamp(1, 1000))
+        .load::<LastWill>(&mut db_connection)
+        .map_err(|error| {
+            error!(
+                "Could not query the last wills of the device {}. The error was: {}",
+                device_id, error
+            );
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(
+        received_last_wills
+            .into_iter()
+            .map(LastWillRecord::from)
+            .collect(),
+    ))
+}
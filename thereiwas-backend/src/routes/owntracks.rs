@@ -1,29 +1,48 @@
 use crate::fairings::ThereIWasDatabaseConnection;
 use crate::guards::AuthenticatedClient;
 use crate::models::{
-    Location, NewLocation, NewLocationToWifiAccessPoint, NewWifiAccessPoint, WifiAccessPoint,
+    Location, NewBeaconSighting, NewDeviceCard, NewDeviceStatus, NewLastWill, NewLocation,
+    NewLocationToWifiAccessPoint, NewRegion, NewStepCount, NewTransition, NewWifiAccessPoint,
+    WifiAccessPoint,
 };
+use crate::routes::commands::take_pending_commands;
+use crate::routes::devices::get_health_callback_client;
+use crate::routes::friends::{get_friend_messages, MAXIMUM_CARD_NAME_LENGTH};
 use crate::routes::guards::RawBody;
 use crate::schema;
+use crate::schema::regions::{
+    created_at as region_created_at_column, reporting_device as region_reporting_device_column,
+};
 use crate::schema::wifi_access_points::dsl::bssid as bssid_column;
 use crate::schema::wifi_access_points::dsl::ssid as ssid_column;
 use crate::schema::wifi_access_points::dsl::wifi_access_points;
 use crate::schema::wifi_access_points::last_seen;
-use chrono::{DateTime, Utc};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use crypto_secretbox::aead::{Aead, KeyInit};
+use crypto_secretbox::{Key, Nonce, XSalsa20Poly1305};
 use diesel::r2d2::ConnectionManager;
 use diesel::result::DatabaseErrorKind;
 use diesel::result::Error::DatabaseError;
-use diesel::{BoolExpressionMethods, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
-use log::{debug, error, info, trace, warn};
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl,
+};
+use log::{debug, error, trace, warn};
 use r2d2::PooledConnection;
-use reqwest::blocking::Client;
 use rocket::http::Status;
+use rocket::serde::json::Json;
 use rocket::{post, State};
 use serde::Deserialize;
+use serde_json::Value;
 use std::error::Error;
 use std::fmt;
 use std::fmt::{Debug, Display, Formatter};
 
+/// The length of the key which is used by libsodium for the secretbox construction
+const KEY_LENGTH: usize = 32;
+/// The length of the nonce which is prepended to every encrypted OwnTracks payload
+const NONCE_LENGTH: usize = 24;
+
 pub enum ReportTrigger {
     /// Ping issued randomly by background task (iOS,Android)
     Ping,
@@ -43,6 +62,8 @@ pub enum ReportTrigger {
     FrequentLocationsMonitoring,
     /// The trigger which was used is not known to the server. Check logs for more information about the report trigger
     UnknownTrigger,
+    /// Imported from a location history which was recorded by another app (server-side only)
+    Import,
 }
 
 impl From<&str> for ReportTrigger {
@@ -57,6 +78,7 @@ impl From<&str> for ReportTrigger {
             "t" => ReportTrigger::TimerBased,
             "v" => ReportTrigger::FrequentLocationsMonitoring,
             "?" => ReportTrigger::UnknownTrigger,
+            "i" => ReportTrigger::Import,
             _ => {
                 error!("Unknown ReportTrigger value: {}", value);
                 ReportTrigger::UnknownTrigger
@@ -65,11 +87,52 @@ impl From<&str> for ReportTrigger {
     }
 }
 
+impl Display for ReportTrigger {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportTrigger::Ping => write!(f, "p"),
+            ReportTrigger::CircularRegion => write!(f, "c"),
+            ReportTrigger::CircularRegionWithFollowRegions => write!(f, "C"),
+            ReportTrigger::BeaconRegion => write!(f, "b"),
+            ReportTrigger::ReportLocationResponse => write!(f, "r"),
+            ReportTrigger::UserRequest => write!(f, "u"),
+            ReportTrigger::TimerBased => write!(f, "t"),
+            ReportTrigger::FrequentLocationsMonitoring => write!(f, "v"),
+            ReportTrigger::UnknownTrigger => write!(f, "?"),
+            ReportTrigger::Import => write!(f, "i"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransitionEvent {
+    /// The device entered the region
+    Enter,
+    /// The device left the region
+    Leave,
+}
+
+impl Display for TransitionEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            TransitionEvent::Enter => write!(f, "enter"),
+            TransitionEvent::Leave => write!(f, "leave"),
+        }
+    }
+}
+
 #[derive(Deserialize)]
 struct GenericRequest {
     #[serde(rename = "_type")]
     pub message_type: String,
 }
+
+#[derive(Deserialize)]
+struct EncryptedRequest {
+    /// The base64 encoded nonce (first 24 bytes) followed by the encrypted message
+    pub data: String,
+}
 #[derive(Deserialize)]
 pub struct StatusRequestApple {
     #[serde(rename = "altimeterAuthorizationStatus")]
@@ -118,25 +181,92 @@ struct StatusRequest {
 struct NewLocationRequest {
     pub lon: f64,
     pub lat: f64,
-    // pub m: i32,
+    pub m: Option<i16>,
     pub tst: i64,
-    // pub bs: Option<u8>,
-    // pub batt: Option<u8>,
+    pub bs: Option<i16>,
+    pub batt: Option<i16>,
     pub acc: Option<i32>,
     pub p: Option<f64>,
     pub vac: Option<i32>,
     pub t: Option<String>,
-    // pub topic: Option<String>,
+    pub topic: Option<String>,
     pub alt: Option<i32>,
-    // pub vel: Option<i32>,
-    // pub cog: Option<i32>,
+    pub vel: Option<i32>,
+    pub cog: Option<i32>,
     pub tid: String,
-    // #[serde(rename = "_type")]
-    // pub message_type: String,
     pub bssid: Option<String>,
     pub ssid: Option<String>,
-    // pub conn: Option<String>,
+    pub conn: Option<String>,
     pub created_at: Option<i64>,
+    pub inregions: Option<Vec<String>>,
+    pub inrids: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct NewTransitionRequest {
+    pub wtst: i64,
+    pub lat: f64,
+    pub lon: f64,
+    pub tst: i64,
+    pub acc: Option<i32>,
+    pub tid: String,
+    pub event: TransitionEvent,
+    pub desc: Option<String>,
+    pub t: Option<String>,
+    pub rid: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct WaypointRequest {
+    pub desc: String,
+    pub lat: f64,
+    pub lon: f64,
+    pub rad: Option<i32>,
+    pub tst: i64,
+    pub rid: Option<String>,
+    pub uuid: Option<String>,
+    pub major: Option<i32>,
+    pub minor: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct LastWillRequest {
+    pub tst: i64,
+}
+
+#[derive(Deserialize)]
+struct BeaconRequest {
+    pub desc: Option<String>,
+    pub uuid: String,
+    pub major: i32,
+    pub minor: i32,
+    pub tst: i64,
+    pub acc: Option<i32>,
+    pub rssi: i32,
+    pub prox: i32,
+}
+
+#[derive(Deserialize)]
+struct StepsRequest {
+    pub tst: i64,
+    pub steps: i32,
+    pub distance: Option<i32>,
+    pub floorsup: Option<i32>,
+    pub floorsdown: Option<i32>,
+    pub from: i64,
+    pub to: i64,
+}
+
+#[derive(Deserialize)]
+struct CardRequest {
+    pub name: String,
+    pub face: Option<String>,
+    pub tid: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct WaypointsRequest {
+    pub waypoints: Vec<WaypointRequest>,
 }
 
 #[derive(Debug)]
@@ -145,10 +275,36 @@ enum OwnTracksError {
     LocationAlreadyKnown,
     /// The combination of the BSSID and the SSID can only be stored once. If this constraint is violated, an error is thrown
     WiFiAPInformationAlreadyKnown,
+    /// Each region transition can only be stored once. If a second request will result in an error
+    TransitionAlreadyKnown,
     /// There was a generic database error while storing an entity. See the logfiles for more information
     GenericDatabaseError,
     /// The request body of the request could not be parsed. See the logfile for more information
     RequestBodyParsingError,
+    /// Each beacon sighting can only be stored once. If a second request will result in an error
+    BeaconAlreadyKnown,
+    /// The steps of a period can only be stored once. If a second request will result in an error
+    StepsAlreadyKnown,
+    /// The type of the message is not supported by the server
+    UnsupportedMessageType,
+    /// An encrypted message was received but no encryption key is configured for the client
+    EncryptionKeyMissing,
+    /// The encrypted message could not be decrypted with the encryption key of the client
+    DecryptionFailed,
+}
+
+impl OwnTracksError {
+    /// Check if the error only reports that the message was already stored before.
+    fn is_already_known(&self) -> bool {
+        matches!(
+            self,
+            OwnTracksError::LocationAlreadyKnown
+                | OwnTracksError::WiFiAPInformationAlreadyKnown
+                | OwnTracksError::TransitionAlreadyKnown
+                | OwnTracksError::BeaconAlreadyKnown
+                | OwnTracksError::StepsAlreadyKnown
+        )
+    }
 }
 
 impl Display for OwnTracksError {
@@ -160,6 +316,9 @@ impl Display for OwnTracksError {
             OwnTracksError::WiFiAPInformationAlreadyKnown => {
                 write!(f, "The provided WiFi access point BSSID/SSID combination is already known")
             }
+            OwnTracksError::TransitionAlreadyKnown => {
+                write!(f, "The provided region transition is already known")
+            }
             OwnTracksError::GenericDatabaseError => write!(
                 f,
                 "There was an generic database error while trying to query or save an entity"
@@ -168,12 +327,39 @@ impl Display for OwnTracksError {
                 f,
                 "There was an error while trying to parse the request body to the expected data type"
             ),
+            OwnTracksError::BeaconAlreadyKnown => {
+                write!(f, "The provided beacon sighting is already known")
+            }
+            OwnTracksError::StepsAlreadyKnown => {
+                write!(f, "The provided step count is already known")
+            }
+            OwnTracksError::UnsupportedMessageType => {
+                write!(f, "The type of the message is not supported")
+            }
+            OwnTracksError::EncryptionKeyMissing => write!(
+                f,
+                "An encrypted message was received but there is no encryption key configured for the client"
+            ),
+            OwnTracksError::DecryptionFailed => write!(
+                f,
+                "The encrypted message could not be decrypted with the configured encryption key"
+            ),
         }
     }
 }
 
 impl Error for OwnTracksError {}
 
+impl From<diesel::result::Error> for OwnTracksError {
+    fn from(error: diesel::result::Error) -> Self {
+        error!(
+            "There was an error while running a database transaction. The error was: {}",
+            error
+        );
+        OwnTracksError::GenericDatabaseError
+    }
+}
+
 fn parse_new_location_request(raw_json: &str) -> Result<NewLocationRequest, OwnTracksError> {
     match serde_json::from_str::<NewLocationRequest>(raw_json) {
         Ok(parsed) => Ok(parsed),
@@ -187,8 +373,23 @@ fn parse_new_location_request(raw_json: &str) -> Result<NewLocationRequest, OwnT
     }
 }
 
-fn parse_status_request(raw_json: &str) -> Result<StatusRequest, OwnTracksError> {
-    match serde_json::from_str::<StatusRequest>(raw_json) {
+/// Convert a time stamp (seconds since the epoch) of an OwnTracks message into a date time. Time
+/// stamps which are out of the supported range are handled like an invalid request body.
+fn get_time_from_timestamp(time_stamp: i64) -> Result<NaiveDateTime, OwnTracksError> {
+    match DateTime::from_timestamp(time_stamp, 0) {
+        Some(date_time) => Ok(date_time.naive_utc()),
+        None => {
+            error!(
+                "Received the time stamp {} which is out of the supported range",
+                time_stamp
+            );
+            Err(OwnTracksError::RequestBodyParsingError)
+        }
+    }
+}
+
+fn parse_new_transition_request(raw_json: &str) -> Result<NewTransitionRequest, OwnTracksError> {
+    match serde_json::from_str::<NewTransitionRequest>(raw_json) {
         Ok(parsed) => Ok(parsed),
         Err(e) => {
             error!(
@@ -200,68 +401,164 @@ fn parse_status_request(raw_json: &str) -> Result<StatusRequest, OwnTracksError>
     }
 }
 
-fn handle_status_request(raw_body: &RawBody) -> Result<(), OwnTracksError> {
-    let body_str = String::from_utf8_lossy(&raw_body.0);
-    let status_request = match parse_status_request(&body_str) {
-        Ok(parsed) => parsed,
+fn parse_waypoint_request(raw_json: &str) -> Result<WaypointRequest, OwnTracksError> {
+    match serde_json::from_str::<WaypointRequest>(raw_json) {
+        Ok(parsed) => Ok(parsed),
         Err(e) => {
-            return Err(e);
+            error!(
+                "Received unknown or invalid JSON received (error was {}): {}",
+                e, raw_json
+            );
+            Err(OwnTracksError::RequestBodyParsingError)
         }
-    };
+    }
+}
+
+fn parse_waypoints_request(raw_json: &str) -> Result<WaypointsRequest, OwnTracksError> {
+    match serde_json::from_str::<WaypointsRequest>(raw_json) {
+        Ok(parsed) => Ok(parsed),
+        Err(e) => {
+            error!(
+                "Received unknown or invalid JSON received (error was {}): {}",
+                e, raw_json
+            );
+            Err(OwnTracksError::RequestBodyParsingError)
+        }
+    }
+}
+
+fn parse_encrypted_request(raw_json: &str) -> Result<EncryptedRequest, OwnTracksError> {
+    match serde_json::from_str::<EncryptedRequest>(raw_json) {
+        Ok(parsed) => Ok(parsed),
+        Err(error) => {
+            error!(
+                "Could not parse the encrypted request. The error was: {}",
+                error
+            );
+            Err(OwnTracksError::RequestBodyParsingError)
+        }
+    }
+}
+
+fn parse_last_will_request(raw_json: &str) -> Result<LastWillRequest, OwnTracksError> {
+    match serde_json::from_str::<LastWillRequest>(raw_json) {
+        Ok(parsed) => Ok(parsed),
+        Err(e) => {
+            error!(
+                "Received unknown or invalid JSON received (error was {}): {}",
+                e, raw_json
+            );
+            Err(OwnTracksError::RequestBodyParsingError)
+        }
+    }
+}
+
+fn parse_beacon_request(raw_json: &str) -> Result<BeaconRequest, OwnTracksError> {
+    match serde_json::from_str::<BeaconRequest>(raw_json) {
+        Ok(parsed) => Ok(parsed),
+        Err(e) => {
+            error!(
+                "Received unknown or invalid JSON received (error was {}): {}",
+                e, raw_json
+            );
+            Err(OwnTracksError::RequestBodyParsingError)
+        }
+    }
+}
+
+fn parse_steps_request(raw_json: &str) -> Result<StepsRequest, OwnTracksError> {
+    match serde_json::from_str::<StepsRequest>(raw_json) {
+        Ok(parsed) => Ok(parsed),
+        Err(e) => {
+            error!(
+                "Received unknown or invalid JSON received (error was {}): {}",
+                e, raw_json
+            );
+            Err(OwnTracksError::RequestBodyParsingError)
+        }
+    }
+}
+
+fn parse_card_request(raw_json: &str) -> Result<CardRequest, OwnTracksError> {
+    match serde_json::from_str::<CardRequest>(raw_json) {
+        Ok(parsed) => Ok(parsed),
+        Err(e) => {
+            error!(
+                "Received unknown or invalid JSON received (error was {}): {}",
+                e, raw_json
+            );
+            Err(OwnTracksError::RequestBodyParsingError)
+        }
+    }
+}
+
+fn parse_status_request(raw_json: &str) -> Result<StatusRequest, OwnTracksError> {
+    match serde_json::from_str::<StatusRequest>(raw_json) {
+        Ok(parsed) => Ok(parsed),
+        Err(e) => {
+            error!(
+                "Received unknown or invalid JSON received (error was {}): {}",
+                e, raw_json
+            );
+            Err(OwnTracksError::RequestBodyParsingError)
+        }
+    }
+}
+
+fn handle_status_request(
+    body_str: &str,
+    reporting_device: i32,
+    db_connection: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), OwnTracksError> {
+    let status_request = parse_status_request(body_str)?;
     trace!("Received a new status request");
 
+    let mut new_record = NewDeviceStatus {
+        reporting_device,
+        request_id: status_request.id,
+        received_at: Utc::now().naive_utc(),
+        ..Default::default()
+    };
     if let Some(ios_status) = status_request.ios {
-        info!("Status information for iOS:");
-        info!(
-            "  Altimeter authorization status: {}",
-            ios_status.altimeter_authorization_status
-        );
-        info!(
-            "  Is relative altitude available: {}",
-            ios_status.altimeter_is_relative_altitude_available
-        );
-        info!(
-            "  Background refresh status: {}",
-            ios_status.background_refresh_status
-        ); // TODO: better description
-        info!(
-            "  Device identifier: {}",
-            ios_status.device_identifier_for_vendor
-        );
-        info!("  Device model: {}", ios_status.device_model);
-        info!("  Device system name: {}", ios_status.device_system_name);
-        info!(
-            "  Device system version: {}",
-            ios_status.device_system_version
-        );
-        info!(
-            "  Device UI idiom: {}",
-            ios_status.device_user_interface_idiom
-        );
-        info!("  Locale: {}", ios_status.locale);
-        info!(
-            "  Locale uses metric system: {}",
-            ios_status.locale_uses_metric_system
-        );
-        info!(
-            "  Location manager authorization status: {}",
-            ios_status.location_manager_authorization_status
-        );
-        info!("  OwnTracks App version: {}", ios_status.version);
+        new_record.altimeter_authorization_status = Some(ios_status.altimeter_authorization_status);
+        new_record.altimeter_is_relative_altitude_available =
+            Some(ios_status.altimeter_is_relative_altitude_available);
+        new_record.background_refresh_status = Some(ios_status.background_refresh_status);
+        new_record.device_identifier_for_vendor = Some(ios_status.device_identifier_for_vendor);
+        new_record.device_model = Some(ios_status.device_model);
+        new_record.device_system_name = Some(ios_status.device_system_name);
+        new_record.device_system_version = Some(ios_status.device_system_version);
+        new_record.device_user_interface_idiom = Some(ios_status.device_user_interface_idiom);
+        new_record.locale = Some(ios_status.locale);
+        new_record.locale_uses_metric_system = Some(ios_status.locale_uses_metric_system);
+        new_record.location_manager_authorization_status =
+            Some(ios_status.location_manager_authorization_status);
+        new_record.app_version = Some(ios_status.version);
     }
     if let Some(android_status) = status_request.android {
-        info!("Status information for Android:");
-        if let Some(request_id) = status_request.id {
-            info!("  Request id: {}", request_id);
-        }
-        info!("  HIB: {}", android_status.hib); // TODO: better description
-        info!("  BO: {}", android_status.bo); // TODO: better description
-        info!("  LOC: {}", android_status.loc); // TODO: better description
-        info!("  PS: {}", android_status.ps); // TODO: better description
-        info!("  WIFI: {}", android_status.wifi); // TODO: better description
+        new_record.app_hibernation = Some(android_status.hib);
+        new_record.battery_optimizations = Some(android_status.bo);
+        new_record.location_permission = Some(android_status.loc);
+        new_record.power_save = Some(android_status.ps);
+        new_record.wifi_state = Some(android_status.wifi);
     }
 
-    Ok(())
+    match diesel::insert_into(schema::device_status::table)
+        .values(&new_record)
+        .execute(db_connection)
+    {
+        Ok(_) => {
+            debug!("Status request stored successfully");
+            Ok(())
+        }
+        Err(error) => {
+            error!(
+                "There was an error while trying to store a status request. The error was: {}",
+                error
+            );
+            Err(OwnTracksError::GenericDatabaseError)
+        }
+    }
 }
 
 fn store_wifi_access_point_association(
@@ -384,12 +681,11 @@ fn fix_owntracks_bssid_error(received_bssid: &str) -> String {
 }
 
 fn handle_new_location_request(
-    raw_body: &RawBody,
+    body_str: &str,
     reporting_device: i32,
     db_connection: &mut PooledConnection<ConnectionManager<PgConnection>>,
 ) -> Result<(), OwnTracksError> {
-    let body_str = String::from_utf8_lossy(&raw_body.0);
-    let location_request = match parse_new_location_request(&body_str) {
+    let location_request = match parse_new_location_request(body_str) {
         Ok(parsed) => parsed,
         Err(e) => {
             return Err(e);
@@ -406,15 +702,27 @@ fn handle_new_location_request(
         latitude: location_request.lat,
         longitude: location_request.lon,
         report_trigger: location_request.t.clone().map_or("?".to_string(), |s| s),
-        measurement_time: DateTime::from_timestamp(location_request.tst, 0)
-            .unwrap()
-            .naive_utc(),
+        measurement_time: get_time_from_timestamp(location_request.tst)?,
         vertical_accuracy: location_request.vac,
         barometric_pressure: location_request.p,
         created_at: location_request
             .created_at
-            .map(|time_stamp| DateTime::from_timestamp(time_stamp, 0).unwrap().naive_utc()),
+            .map(get_time_from_timestamp)
+            .transpose()?,
         reporting_device,
+        battery_level: location_request.batt,
+        battery_status: location_request.bs,
+        monitoring_mode: location_request.m,
+        velocity: location_request.vel,
+        course_over_ground: location_request.cog,
+        connection_type: location_request.conn,
+        topic: location_request.topic,
+        in_regions: location_request
+            .inregions
+            .map(|regions| regions.into_iter().map(Some).collect()),
+        in_region_ids: location_request
+            .inrids
+            .map(|region_ids| region_ids.into_iter().map(Some).collect()),
     };
 
     let query_result = diesel::insert_into(schema::locations::table)
@@ -465,22 +773,324 @@ fn handle_new_location_request(
     Ok(())
 }
 
-#[post("/owntracks", data = "<raw_body>")]
-pub fn add_new_location_record(
-    db_connection_pool: &State<ThereIWasDatabaseConnection>,
-    raw_body: RawBody,
-    authenticated_client: AuthenticatedClient,
-) -> Status {
-    let body_str = String::from_utf8_lossy(&raw_body.0);
-    trace!("Received following JSON: {}", body_str);
-    let generic_request = match serde_json::from_slice::<GenericRequest>(&raw_body.0) {
+fn handle_new_transition_request(
+    body_str: &str,
+    reporting_device: i32,
+    db_connection: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), OwnTracksError> {
+    let transition_request = parse_new_transition_request(body_str)?;
+    trace!(
+        "Received a new transition request with the tid of {}",
+        transition_request.tid
+    );
+
+    let new_record = NewTransition {
+        event: transition_request.event.to_string(),
+        region_id: transition_request.rid,
+        region_description: transition_request.desc,
+        region_created_at: get_time_from_timestamp(transition_request.wtst)?,
+        report_trigger: transition_request.t.unwrap_or("?".to_string()),
+        horizontal_accuracy: transition_request.acc,
+        latitude: transition_request.lat,
+        longitude: transition_request.lon,
+        measurement_time: get_time_from_timestamp(transition_request.tst)?,
+        reporting_device,
+    };
+
+    match diesel::insert_into(schema::transitions::table)
+        .values(&new_record)
+        .execute(db_connection)
+    {
+        Ok(_) => {
+            debug!("Transition request stored successfully");
+            Ok(())
+        }
+        Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            error!(
+                "Could not store the transition request since the transition was already submitted"
+            );
+            Err(OwnTracksError::TransitionAlreadyKnown)
+        }
+        Err(error) => {
+            error!(
+                "There was an error while trying to store a transition request. The error was: {}",
+                error
+            );
+            Err(OwnTracksError::GenericDatabaseError)
+        }
+    }
+}
+
+fn store_waypoint(
+    waypoint: WaypointRequest,
+    reporting_device: i32,
+    db_connection: &mut PgConnection,
+) -> Result<(), OwnTracksError> {
+    let new_region = NewRegion {
+        reporting_device,
+        region_id: waypoint.rid,
+        description: waypoint.desc,
+        latitude: waypoint.lat,
+        longitude: waypoint.lon,
+        radius: waypoint.rad.unwrap_or(0),
+        beacon_uuid: waypoint.uuid,
+        beacon_major: waypoint.major,
+        beacon_minor: waypoint.minor,
+        created_at: get_time_from_timestamp(waypoint.tst)?,
+    };
+
+    // OwnTracks re-publishes all regions whenever they change, so we have to update the already
+    // known ones instead of failing on the unique constraint
+    diesel::insert_into(schema::regions::table)
+        .values(&new_region)
+        .on_conflict((region_reporting_device_column, region_created_at_column))
+        .do_update()
+        .set(&new_region)
+        .execute(db_connection)
+        .map(|_| ())
+        .map_err(|error| {
+            error!(
+                "There was an error while trying to store the region '{}'. The error was: {}",
+                new_region.description, error
+            );
+            OwnTracksError::GenericDatabaseError
+        })
+}
+
+fn handle_waypoint_request(
+    body_str: &str,
+    reporting_device: i32,
+    db_connection: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), OwnTracksError> {
+    let waypoint_request = parse_waypoint_request(body_str)?;
+    trace!(
+        "Received a new waypoint request for the region '{}'",
+        waypoint_request.desc
+    );
+
+    store_waypoint(waypoint_request, reporting_device, db_connection)?;
+    debug!("Waypoint request stored successfully");
+    Ok(())
+}
+
+fn handle_waypoints_request(
+    body_str: &str,
+    reporting_device: i32,
+    db_connection: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), OwnTracksError> {
+    let waypoints_request = parse_waypoints_request(body_str)?;
+    trace!(
+        "Received a new waypoints request with {} regions",
+        waypoints_request.waypoints.len()
+    );
+
+    db_connection.transaction::<_, OwnTracksError, _>(|connection| {
+        for waypoint in waypoints_request.waypoints {
+            store_waypoint(waypoint, reporting_device, connection)?;
+        }
+        Ok(())
+    })?;
+    debug!("Waypoints request stored successfully");
+    Ok(())
+}
+
+fn handle_last_will_request(
+    body_str: &str,
+    reporting_device: i32,
+    db_connection: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), OwnTracksError> {
+    let last_will_request = parse_last_will_request(body_str)?;
+    trace!("Received a new last will request");
+
+    // the same last will might be sent multiple times, so we just keep the first one
+    diesel::insert_into(schema::last_wills::table)
+        .values(&NewLastWill {
+            connected_at: get_time_from_timestamp(last_will_request.tst)?,
+            received_at: Utc::now().naive_utc(),
+            reporting_device,
+        })
+        .on_conflict_do_nothing()
+        .execute(db_connection)?;
+    debug!("Last will request stored successfully");
+    Ok(())
+}
+
+fn handle_beacon_request(
+    body_str: &str,
+    reporting_device: i32,
+    db_connection: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), OwnTracksError> {
+    let beacon_request = parse_beacon_request(body_str)?;
+    trace!(
+        "Received a new beacon request for the beacon {}:{}:{}",
+        beacon_request.uuid,
+        beacon_request.major,
+        beacon_request.minor
+    );
+
+    let new_record = NewBeaconSighting {
+        description: beacon_request.desc,
+        beacon_uuid: beacon_request.uuid,
+        beacon_major: beacon_request.major,
+        beacon_minor: beacon_request.minor,
+        proximity: beacon_request.prox,
+        rssi: beacon_request.rssi,
+        horizontal_accuracy: beacon_request.acc,
+        measurement_time: get_time_from_timestamp(beacon_request.tst)?,
+        reporting_device,
+    };
+
+    match diesel::insert_into(schema::beacon_sightings::table)
+        .values(&new_record)
+        .execute(db_connection)
+    {
+        Ok(_) => {
+            debug!("Beacon request stored successfully");
+            Ok(())
+        }
+        Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            error!("Could not store the beacon request since the sighting was already submitted");
+            Err(OwnTracksError::BeaconAlreadyKnown)
+        }
+        Err(error) => {
+            error!(
+                "There was an error while trying to store a beacon request. The error was: {}",
+                error
+            );
+            Err(OwnTracksError::GenericDatabaseError)
+        }
+    }
+}
+
+fn handle_steps_request(
+    body_str: &str,
+    reporting_device: i32,
+    db_connection: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), OwnTracksError> {
+    let steps_request = parse_steps_request(body_str)?;
+    trace!("Received a new steps request");
+
+    // the app reports -1 if a value is not available on the device
+    let available = |value: Option<i32>| value.filter(|value| *value >= 0);
+    let new_record = NewStepCount {
+        steps: steps_request.steps,
+        distance: available(steps_request.distance),
+        floors_up: available(steps_request.floorsup),
+        floors_down: available(steps_request.floorsdown),
+        period_start: get_time_from_timestamp(steps_request.from)?,
+        period_end: get_time_from_timestamp(steps_request.to)?,
+        measurement_time: get_time_from_timestamp(steps_request.tst)?,
+        reporting_device,
+    };
+
+    match diesel::insert_into(schema::step_counts::table)
+        .values(&new_record)
+        .execute(db_connection)
+    {
+        Ok(_) => {
+            debug!("Steps request stored successfully");
+            Ok(())
+        }
+        Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            error!("Could not store the steps request since the period was already submitted");
+            Err(OwnTracksError::StepsAlreadyKnown)
+        }
+        Err(error) => {
+            error!(
+                "There was an error while trying to store a steps request. The error was: {}",
+                error
+            );
+            Err(OwnTracksError::GenericDatabaseError)
+        }
+    }
+}
+
+fn handle_card_request(
+    body_str: &str,
+    reporting_device: i32,
+    db_connection: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), OwnTracksError> {
+    let card_request = parse_card_request(body_str)?;
+    trace!("Received a new card request for '{}'", card_request.name);
+    if card_request.name.chars().count() > MAXIMUM_CARD_NAME_LENGTH
+        || card_request
+            .tid
+            .as_ref()
+            .is_some_and(|tracker_id| tracker_id.chars().count() > 2)
+    {
+        error!(
+            "Received a card request with a name or tracker id which is too long: {}",
+            body_str
+        );
+        return Err(OwnTracksError::RequestBodyParsingError);
+    }
+
+    let new_card = NewDeviceCard {
+        reporting_device,
+        tracker_id: card_request.tid,
+        name: card_request.name,
+        face: card_request.face,
+    };
+
+    diesel::insert_into(schema::device_cards::table)
+        .values(&new_card)
+        .on_conflict(schema::device_cards::reporting_device)
+        .do_update()
+        .set(&new_card)
+        .execute(db_connection)?;
+    debug!("Card request stored successfully");
+    Ok(())
+}
+
+/// Decrypt a payload which was encrypted by the OwnTracks apps. The apps use the libsodium
+/// secretbox (XSalsa20-Poly1305) construction and derive the key by zero-padding (or truncating)
+/// the configured secret to 32 bytes.
+fn decrypt_payload(data: &str, encryption_key: &str) -> Result<String, OwnTracksError> {
+    let encrypted_data = BASE64_STANDARD.decode(data.trim()).map_err(|error| {
+        error!(
+            "The encrypted payload is not valid base64. The error was: {}",
+            error
+        );
+        OwnTracksError::RequestBodyParsingError
+    })?;
+    if encrypted_data.len() < NONCE_LENGTH {
+        error!("The encrypted payload is too short to contain a nonce");
+        return Err(OwnTracksError::RequestBodyParsingError);
+    }
+
+    let mut key = [0u8; KEY_LENGTH];
+    let key_bytes = encryption_key.as_bytes();
+    let used_key_length = key_bytes.len().min(KEY_LENGTH);
+    key[..used_key_length].copy_from_slice(&key_bytes[..used_key_length]);
+
+    let (nonce, cipher_text) = encrypted_data.split_at(NONCE_LENGTH);
+    let plain_text = XSalsa20Poly1305::new(Key::from_slice(&key))
+        .decrypt(Nonce::from_slice(nonce), cipher_text)
+        .map_err(|_| OwnTracksError::DecryptionFailed)?;
+
+    String::from_utf8(plain_text).map_err(|error| {
+        error!(
+            "The decrypted payload is not valid UTF-8. The error was: {}",
+            error
+        );
+        OwnTracksError::RequestBodyParsingError
+    })
+}
+
+fn handle_message(
+    body_str: &str,
+    authenticated_client: &AuthenticatedClient,
+    allow_encrypted_message: bool,
+    db_connection: &mut PooledConnection<ConnectionManager<PgConnection>>,
+) -> Result<(), OwnTracksError> {
+    let generic_request = match serde_json::from_str::<GenericRequest>(body_str) {
         Ok(parsed) => parsed,
         Err(e) => {
             error!(
                 "The received request body can not be interpreted (error was {}): {}",
                 e, body_str
             );
-            return Status::UnprocessableEntity;
+            return Err(OwnTracksError::RequestBodyParsingError);
         }
     };
     debug!(
@@ -488,28 +1098,84 @@ pub fn add_new_location_record(
         generic_request.message_type
     );
 
-    let mut db_connection = db_connection_pool.get().unwrap();
-
-    let message_handling_result = match generic_request.message_type.as_str() {
-        "location" => {
-            handle_new_location_request(&raw_body, authenticated_client.id, &mut db_connection)
+    match generic_request.message_type.as_str() {
+        "location" => handle_new_location_request(body_str, authenticated_client.id, db_connection),
+        "status" => handle_status_request(body_str, authenticated_client.id, db_connection),
+        "transition" => {
+            handle_new_transition_request(body_str, authenticated_client.id, db_connection)
+        }
+        "waypoint" => handle_waypoint_request(body_str, authenticated_client.id, db_connection),
+        "waypoints" => handle_waypoints_request(body_str, authenticated_client.id, db_connection),
+        "lwt" => handle_last_will_request(body_str, authenticated_client.id, db_connection),
+        "beacon" => handle_beacon_request(body_str, authenticated_client.id, db_connection),
+        "steps" => handle_steps_request(body_str, authenticated_client.id, db_connection),
+        "card" => handle_card_request(body_str, authenticated_client.id, db_connection),
+        "encrypted" if allow_encrypted_message => {
+            let Some(encryption_key) = authenticated_client.encryption_key.as_ref() else {
+                error!(
+                    "Received an encrypted message from client {} but there is no encryption key configured for it",
+                    authenticated_client.id
+                );
+                return Err(OwnTracksError::EncryptionKeyMissing);
+            };
+            let encrypted_request = parse_encrypted_request(body_str)?;
+            let decrypted_body = decrypt_payload(&encrypted_request.data, encryption_key)
+                .inspect_err(|error| {
+                    if let OwnTracksError::DecryptionFailed = error {
+                        error!(
+                            "Could not decrypt the message of client {}. Most likely the encryption key configured for the client does not match the secret configured in the app",
+                            authenticated_client.id
+                        );
+                    }
+                })?;
+            trace!("Decrypted message: {}", decrypted_body);
+            handle_message(&decrypted_body, authenticated_client, false, db_connection)
         }
-        "status" => handle_status_request(&raw_body),
         _ => {
             warn!(
                 "There is no implementation for handling {} requests yet",
                 generic_request.message_type
             );
-            return Status::BadRequest;
+            Err(OwnTracksError::UnsupportedMessageType)
         }
-    };
+    }
+}
+
+#[post("/owntracks", data = "<raw_body>")]
+pub fn add_new_location_record(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    raw_body: RawBody,
+    authenticated_client: AuthenticatedClient,
+) -> Result<Json<Vec<Value>>, Status> {
+    let body_str = String::from_utf8_lossy(&raw_body.0);
+    trace!("Received following JSON: {}", body_str);
 
-    if message_handling_result.is_ok() && authenticated_client.health_callback_url.is_some() {
-        let client = Client::new();
-        match client
-            .get(&authenticated_client.health_callback_url.unwrap())
-            .send()
-        {
+    let mut db_connection = db_connection_pool.get().unwrap();
+
+    // the apps send a message again until it gets accepted and the messages which were queued
+    // after it have to wait until then, so a message which was already stored is accepted as well
+    let message_handling_result =
+        match handle_message(&body_str, &authenticated_client, true, &mut db_connection) {
+            Err(error) if error.is_already_known() => {
+                debug!(
+                    "Accepting the message of the client {} which was already stored before ({})",
+                    authenticated_client.id, error
+                );
+                Ok(())
+            }
+            message_handling_result => message_handling_result,
+        };
+
+    if let (Ok(_), Some(health_callback_url)) = (
+        &message_handling_result,
+        &authenticated_client.health_callback_url,
+    ) {
+        match get_health_callback_client(health_callback_url).and_then(|client| {
+            client
+                .get(health_callback_url)
+                .send()
+                .map_err(|error| error.to_string())
+        }) {
             Ok(_) => {
                 debug!(
                     "Successfully called health callback URL for client {}",
@@ -525,15 +1191,45 @@ pub fn add_new_location_record(
         }
     }
 
-    match message_handling_result {
-        Ok(_) => Status::NoContent,
-        Err(error) => match error {
+    if let Err(error) = message_handling_result {
+        return Err(match error {
             OwnTracksError::LocationAlreadyKnown => Status::Conflict,
             OwnTracksError::WiFiAPInformationAlreadyKnown => Status::Conflict,
+            OwnTracksError::TransitionAlreadyKnown => Status::Conflict,
+            OwnTracksError::BeaconAlreadyKnown => Status::Conflict,
+            OwnTracksError::StepsAlreadyKnown => Status::Conflict,
             OwnTracksError::RequestBodyParsingError => Status::UnprocessableEntity,
             OwnTracksError::GenericDatabaseError => Status::InternalServerError,
-        },
+            OwnTracksError::UnsupportedMessageType => Status::BadRequest,
+            OwnTracksError::EncryptionKeyMissing => Status::BadRequest,
+            OwnTracksError::DecryptionFailed => Status::Forbidden,
+        });
     }
+
+    // OwnTracks accepts a list of messages (e.g. commands or the locations of friends) as a
+    // response body which will be processed by the app as if they were received from a MQTT broker
+    let mut response_messages =
+        match take_pending_commands(authenticated_client.id, &mut db_connection) {
+            Ok(pending_commands) => pending_commands,
+            Err(error) => {
+                error!(
+                    "Could not get the pending commands for the client {}. The error was: {}",
+                    authenticated_client.id, error
+                );
+                vec![]
+            }
+        };
+    match get_friend_messages(authenticated_client.id, &mut db_connection) {
+        Ok(friend_messages) => response_messages.extend(friend_messages),
+        Err(error) => {
+            error!(
+                "Could not get the locations of the friends of the client {}. The error was: {}",
+                authenticated_client.id, error
+            );
+        }
+    }
+
+    Ok(Json(response_messages))
 }
 
 #[cfg(test)]
@@ -543,13 +1239,107 @@ mod tests {
     #[test]
     fn test_check_lowercase_ssid_and_bssid_are_parsed_correctly() {
         let input_text = r#"{"tid":"6C","batt":75,"lon":5.1234560000000000,"acc":6,"bs":1,"inrids":[],"p":102.283,"vac":3,"inregions":[],"lat":40.123456000000000,"topic":"owntracks\/user\/7CB9781C-BA94-4BB2-B2F8-CFA8E99BFB61","bssid":"de:ad:be:ef:00:00","t":"u","conn":"w","tst":1742196210,"m":2,"ssid":"some ssid","alt":35,"_type":"location"}"#;
-        let parse_result = parse_new_location_request(&input_text);
+        let parse_result = parse_new_location_request(input_text);
 
-        assert_eq!(parse_result.is_ok(), true);
+        assert!(parse_result.is_ok());
         let unwrapped = parse_result.unwrap();
-        assert_eq!(unwrapped.bssid.is_some(), true);
+        assert!(unwrapped.bssid.is_some());
         assert_eq!(unwrapped.bssid.unwrap(), "de:ad:be:ef:00:00");
-        assert_eq!(unwrapped.ssid.is_some(), true);
+        assert!(unwrapped.ssid.is_some());
         assert_eq!(unwrapped.ssid.unwrap(), "some ssid");
     }
+
+    #[test]
+    fn test_battery_and_movement_information_is_parsed_correctly() {
+        let input_text = r#"{"cog":336,"batt":60,"lon":6.775328,"acc":5,"bs":2,"p":103.155,"vel":4,"vac":3,"lat":51.21083,"inregions":["Home"],"inrids":["b07bd3"],"topic":"owntracks\/user\/5445D668-ADDE-4BC2-A63F-7880FAD1B96C","t":"v","conn":"m","m":1,"tst":1735219355,"alt":38,"_type":"location","tid":"6C"}"#;
+        let parse_result = parse_new_location_request(input_text);
+
+        assert!(parse_result.is_ok());
+        let unwrapped = parse_result.unwrap();
+        assert_eq!(unwrapped.batt, Some(60));
+        assert_eq!(unwrapped.bs, Some(2));
+        assert_eq!(unwrapped.m, Some(1));
+        assert_eq!(unwrapped.vel, Some(4));
+        assert_eq!(unwrapped.cog, Some(336));
+        assert_eq!(unwrapped.conn.as_deref(), Some("m"));
+        assert_eq!(unwrapped.inregions, Some(vec!["Home".to_string()]));
+        assert_eq!(unwrapped.inrids, Some(vec!["b07bd3".to_string()]));
+    }
+
+    #[test]
+    fn test_transition_request_is_parsed_correctly() {
+        let input_text = r#"{"_type":"transition","wtst":1735477260,"lat":51.210665,"lon":6.779147,"tst":1735480000,"acc":12,"tid":"6C","event":"leave","desc":"Here-b07bd3","t":"c","rid":"b07bd3"}"#;
+        let parse_result = parse_new_transition_request(input_text);
+
+        assert!(parse_result.is_ok());
+        let unwrapped = parse_result.unwrap();
+        assert_eq!(unwrapped.event.to_string(), "leave");
+        assert_eq!(unwrapped.rid.unwrap(), "b07bd3");
+        assert_eq!(unwrapped.desc.unwrap(), "Here-b07bd3");
+        assert_eq!(unwrapped.wtst, 1735477260);
+    }
+
+    #[test]
+    fn test_out_of_range_time_stamps_are_rejected() {
+        assert!(get_time_from_timestamp(1735480000).is_ok());
+        assert!(get_time_from_timestamp(i64::MAX).is_err());
+    }
+
+    #[test]
+    fn test_transition_request_with_unknown_event_is_rejected() {
+        let input_text = r#"{"_type":"transition","wtst":1735477260,"lat":51.210665,"lon":6.779147,"tst":1735480000,"tid":"6C","event":"stay"}"#;
+        assert!(parse_new_transition_request(input_text).is_err());
+    }
+
+    #[test]
+    fn test_waypoints_request_is_parsed_correctly() {
+        let input_text = r#"{"_type":"waypoints","topic":"owntracks\/user\/5445D668-ADDE-4BC2-A63F-7880FAD1B96C\/waypoints","waypoints":[{"rad":10,"tst":1735477260,"_type":"waypoint","rid":"b07bd3","lon":6.779147,"lat":51.210664999999999,"desc":"Here-b07bd3"},{"_type":"waypoint","tst":1735477277,"lon":6.779147,"lat":51.210665,"desc":"Office","uuid":"CA271EAE-5FA8-4E80-8F08-2A302A95A959","major":1,"minor":2}]}"#;
+        let parse_result = parse_waypoints_request(input_text);
+
+        assert!(parse_result.is_ok());
+        let unwrapped = parse_result.unwrap();
+        assert_eq!(unwrapped.waypoints.len(), 2);
+        assert_eq!(unwrapped.waypoints[0].rad, Some(10));
+        assert_eq!(unwrapped.waypoints[0].rid.as_deref(), Some("b07bd3"));
+        assert!(unwrapped.waypoints[1].rad.is_none());
+        assert_eq!(unwrapped.waypoints[1].major, Some(1));
+        assert_eq!(unwrapped.waypoints[1].minor, Some(2));
+    }
+
+    #[test]
+    fn test_encrypted_payload_is_decrypted_with_padded_key() {
+        let plain_text = r#"{"_type":"location","lat":51.210665,"lon":6.779147,"tst":1735480000}"#;
+        let mut key = [0u8; KEY_LENGTH];
+        key[..6].copy_from_slice(b"secret");
+        let nonce = [7u8; NONCE_LENGTH];
+        let cipher_text = XSalsa20Poly1305::new(Key::from_slice(&key))
+            .encrypt(Nonce::from_slice(&nonce), plain_text.as_bytes())
+            .unwrap();
+        let data = BASE64_STANDARD.encode([nonce.as_slice(), cipher_text.as_slice()].concat());
+
+        assert_eq!(decrypt_payload(&data, "secret").unwrap(), plain_text);
+        assert!(matches!(
+            decrypt_payload(&data, "another secret"),
+            Err(OwnTracksError::DecryptionFailed)
+        ));
+        assert!(matches!(
+            decrypt_payload("AAAA", "secret"),
+            Err(OwnTracksError::RequestBodyParsingError)
+        ));
+    }
+
+    #[test]
+    fn test_beacon_and_steps_requests_are_parsed_correctly() {
+        let beacon_text = r#"{"_type":"beacon","desc":"Office","uuid":"CA271EAE-5FA8-4E80-8F08-2A302A95A959","major":1,"minor":2,"tst":1735480000,"acc":3,"rssi":-67,"prox":2}"#;
+        let beacon = parse_beacon_request(beacon_text).unwrap();
+        assert_eq!(beacon.major, 1);
+        assert_eq!(beacon.rssi, -67);
+        assert_eq!(beacon.prox, 2);
+
+        let steps_text = r#"{"_type":"steps","tst":1735480000,"steps":4321,"distance":3100,"floorsup":-1,"floorsdown":-1,"from":1735430400,"to":1735480000}"#;
+        let steps = parse_steps_request(steps_text).unwrap();
+        assert_eq!(steps.steps, 4321);
+        assert_eq!(steps.floorsup, Some(-1));
+        assert_eq!(steps.to, 1735480000);
+    }
 }
@@ -0,0 +1,311 @@
+use crate::fairings::ThereIWasDatabaseConnection;
+use crate::guards::AuthenticatedUser;
+use crate::models::{NewRegion, Region};
+use crate::routes::commands::queue_waypoints_command;
+use crate::schema::regions::dsl::regions;
+use crate::schema::regions::{created_at, id as region_id_column, reporting_device};
+use chrono::{DateTime, NaiveDateTime, SubsecRound, TimeDelta, Utc};
+use diesel::result::DatabaseErrorKind;
+use diesel::result::Error::DatabaseError;
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl,
+};
+use log::error;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{delete, get, options, post, put, State};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize)]
+pub struct RegionRecord {
+    pub id: i32,
+    pub region_id: Option<String>,
+    pub description: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius: i32,
+    pub beacon_uuid: Option<String>,
+    pub beacon_major: Option<i32>,
+    pub beacon_minor: Option<i32>,
+    pub created_at: i64,
+}
+
+impl From<Region> for RegionRecord {
+    fn from(region: Region) -> Self {
+        RegionRecord {
+            id: region.id,
+            region_id: region.region_id,
+            description: region.description,
+            latitude: region.latitude,
+            longitude: region.longitude,
+            radius: region.radius,
+            beacon_uuid: region.beacon_uuid,
+            beacon_major: region.beacon_major,
+            beacon_minor: region.beacon_minor,
+            created_at: region.created_at.and_utc().timestamp(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RegionInformation {
+    /// The rid which is used by OwnTracks to reference the region.
+    region_id: Option<String>,
+    /// The name of the region.
+    description: String,
+    /// The latitude of the center of the region.
+    latitude: f64,
+    /// The longitude of the center of the region.
+    longitude: f64,
+    /// The radius of the region in meters.
+    radius: i32,
+    /// The UUID of the iBeacon which defines the region (if any).
+    beacon_uuid: Option<String>,
+    /// The major number of the iBeacon which defines the region (if any).
+    beacon_major: Option<i32>,
+    /// The minor number of the iBeacon which defines the region (if any).
+    beacon_minor: Option<i32>,
+    /// The time the region was created in seconds since the epoch (the `wtst` of OwnTracks). A new
+    /// region is created at the current time and an updated region keeps its time if it is not
+    /// supplied.
+    #[serde(default)]
+    created_at: Option<i64>,
+}
+
+impl RegionInformation {
+    fn is_valid(&self) -> bool {
+        !self.description.is_empty()
+            && (-90.0..=90.0).contains(&self.latitude)
+            && (-180.0..=180.0).contains(&self.longitude)
+            && self.radius >= 0
+    }
+
+    /// Get the supplied creation time of the region, if any.
+    fn get_created_at(&self) -> Result<Option<NaiveDateTime>, Status> {
+        self.created_at
+            .map(|time_stamp| {
+                DateTime::from_timestamp(time_stamp, 0)
+                    .map(|time| time.naive_utc())
+                    .ok_or(Status::UnprocessableEntity)
+            })
+            .transpose()
+    }
+}
+
+/// Get the creation time for a new region of the supplied device. OwnTracks identifies a region
+/// by its creation time in seconds, so the next free second is used if another region was already
+/// created in the current one.
+fn get_creation_time_for_new_region(
+    device_id: i32,
+    db_connection: &mut PgConnection,
+) -> Result<NaiveDateTime, diesel::result::Error> {
+    let now = Utc::now().naive_utc().trunc_subsecs(0);
+    let latest_creation_time = regions
+        .filter(reporting_device.eq(device_id))
+        .select(diesel::dsl::max(created_at))
+        .first::<Option<NaiveDateTime>>(db_connection)?;
+    Ok(match latest_creation_time {
+        Some(latest_creation_time) if latest_creation_time >= now => {
+            latest_creation_time.trunc_subsecs(0) + TimeDelta::seconds(1)
+        }
+        _ => now,
+    })
+}
+
+#[options("/devices/<_device_id>/regions")]
+pub fn get_regions_options(_device_id: i32) -> Status {
+    Status::Ok
+}
+
+#[options("/devices/<_device_id>/regions/<_region>")]
+pub fn get_region_options(_device_id: i32, _region: i32) -> Status {
+    Status::Ok
+}
+
+#[get("/devices/<device_id>/regions")]
+pub fn get_regions(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+) -> Result<Json<Vec<RegionRecord>>, Status> {
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+
+    let region_records = regions
+        .filter(reporting_device.eq(device_id))
+        .order_by(created_at.asc())
+        .load::<Region>(&mut db_connection)
+        .map_err(|error| {
+            error!(
+                "Could not query the regions of the device {}. The error was: {}",
+                device_id, error
+            );
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(
+        region_records.into_iter().map(RegionRecord::from).collect(),
+    ))
+}
+
+#[post("/devices/<device_id>/regions", data = "<region_information>")]
+pub fn add_region(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+    region_information: Json<RegionInformation>,
+) -> Result<Json<RegionRecord>, Status> {
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+    if !region_information.is_valid() {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let region_information = region_information.into_inner();
+    let region_created_at = match region_information.get_created_at()? {
+        Some(region_created_at) => region_created_at,
+        None => {
+            get_creation_time_for_new_region(device_id, &mut db_connection).map_err(|error| {
+                error!(
+                    "Could not query the regions of the device {}. The error was: {}",
+                    device_id, error
+                );
+                Status::InternalServerError
+            })?
+        }
+    };
+    let new_region = NewRegion {
+        reporting_device: device_id,
+        region_id: region_information.region_id,
+        description: region_information.description,
+        latitude: region_information.latitude,
+        longitude: region_information.longitude,
+        radius: region_information.radius,
+        beacon_uuid: region_information.beacon_uuid,
+        beacon_major: region_information.beacon_major,
+        beacon_minor: region_information.beacon_minor,
+        created_at: region_created_at,
+    };
+
+    match db_connection.transaction::<_, diesel::result::Error, _>(|connection| {
+        let region = diesel::insert_into(crate::schema::regions::table)
+            .values(&new_region)
+            .get_result::<Region>(connection)?;
+        queue_waypoints_command(device_id, connection)?;
+        Ok(region)
+    }) {
+        Ok(region) => Ok(Json(RegionRecord::from(region))),
+        Err(DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Err(Status::Conflict),
+        // users who can access all devices pass the access check even for unknown devices
+        Err(DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => Err(Status::NotFound),
+        Err(error) => {
+            error!(
+                "Could not store the new region for the device {}. The error was: {}",
+                device_id, error
+            );
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[put("/devices/<device_id>/regions/<region>", data = "<region_information>")]
+pub fn update_region(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+    region: i32,
+    region_information: Json<RegionInformation>,
+) -> Result<Json<RegionRecord>, Status> {
+    use crate::schema::regions::{
+        beacon_major, beacon_minor, beacon_uuid, description, latitude, longitude, radius,
+        region_id,
+    };
+
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+    if !region_information.is_valid() {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let region_information = region_information.into_inner();
+    let region_created_at = region_information.get_created_at()?;
+    db_connection
+        .transaction::<_, diesel::result::Error, _>(|connection| {
+            let region_filter = regions.filter(
+                region_id_column
+                    .eq(region)
+                    .and(reporting_device.eq(device_id)),
+            );
+            let changed_region = diesel::update(region_filter)
+                .set((
+                    region_id.eq(region_information.region_id),
+                    description.eq(region_information.description),
+                    latitude.eq(region_information.latitude),
+                    longitude.eq(region_information.longitude),
+                    radius.eq(region_information.radius),
+                    beacon_uuid.eq(region_information.beacon_uuid),
+                    beacon_major.eq(region_information.beacon_major),
+                    beacon_minor.eq(region_information.beacon_minor),
+                    region_created_at.map(|region_created_at| created_at.eq(region_created_at)),
+                ))
+                .get_result::<Region>(connection)?;
+            queue_waypoints_command(device_id, connection)?;
+            Ok(changed_region)
+        })
+        .map(|region| Json(RegionRecord::from(region)))
+        .map_err(|error| match error {
+            diesel::result::Error::NotFound => Status::NotFound,
+            DatabaseError(DatabaseErrorKind::UniqueViolation, _) => Status::Conflict,
+            _ => {
+                error!(
+                    "Could not update the region {} of the device {}. The error was: {}",
+                    region, device_id, error
+                );
+                Status::InternalServerError
+            }
+        })
+}
+
+#[delete("/devices/<device_id>/regions/<region>")]
+pub fn delete_region(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+    region: i32,
+) -> Result<Status, Status> {
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+
+    match db_connection.transaction::<_, diesel::result::Error, _>(|connection| {
+        let deleted_regions = diesel::delete(
+            regions.filter(
+                region_id_column
+                    .eq(region)
+                    .and(reporting_device.eq(device_id)),
+            ),
+        )
+        .execute(connection)?;
+        if deleted_regions > 0 {
+            queue_waypoints_command(device_id, connection)?;
+        }
+        Ok(deleted_regions)
+    }) {
+        Ok(0) => Err(Status::NotFound),
+        Ok(_) => Ok(Status::NoContent),
+        Err(error) => {
+            error!(
+                "Could not delete the region {} of the device {}. The error was: {}",
+                region, device_id, error
+            );
+            Err(Status::InternalServerError)
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::fairings::ThereIWasDatabaseConnection;
+use crate::guards::AuthenticatedUser;
+use crate::models::StepCount;
+use crate::schema::step_counts::dsl::step_counts;
+use crate::schema::step_counts::{period_start, reporting_device};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use log::error;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{get, options, State};
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct StepCountRecord {
+    pub steps: i32,
+    pub distance: Option<i32>,
+    pub floors_up: Option<i32>,
+    pub floors_down: Option<i32>,
+    pub period_start: i64,
+    pub period_end: i64,
+    pub measurement_time: i64,
+}
+
+impl From<StepCount> for StepCountRecord {
+    fn from(step_count: StepCount) -> Self {
+        StepCountRecord {
+            steps: step_count.steps,
+            distance: step_count.distance,
+            floors_up: step_count.floors_up,
+            floors_down: step_count.floors_down,
+            period_start: step_count.period_start.and_utc().timestamp(),
+            period_end: step_count.period_end.and_utc().timestamp(),
+            measurement_time: step_count.measurement_time.and_utc().timestamp(),
+        }
+    }
+}
+
+#[options("/devices/<_device_id>/steps")]
+pub fn get_step_counts_options(_device_id: i32) -> Status {
+    Status::Ok
+}
+
+#[get("/devices/<device_id>/steps?<limit>")]
+pub fn get_step_counts(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+    limit: Option<i64>,
+) -> Result<Json<Vec<StepCountRecord>>, Status> {
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+
+    let reported_steps = step_counts
+        .filter(reporting_device.eq(device_id))
+        .order_by(period_start.desc())
+        .limit(limit.unwrap_or(100).clamp(1, 1000))
+        .load::<StepCount>(&mut db_connection)
+        .map_err(|error| {
+            error!(
+                "Could not query the step counts of the device {}. The error was: {}",
+                device_id, error
+            );
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(
+        reported_steps
+            .into_iter()
+            .map(StepCountRecord::from)
+            .collect(),
+    ))
+}
@@ -0,0 +1,86 @@
+use crate::fairings::ThereIWasDatabaseConnection;
+use crate::guards::AuthenticatedUser;
+use crate::models::Transition;
+use crate::schema::transitions::dsl::transitions;
+use crate::schema::transitions::{measurement_time, region_id, reporting_device};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use log::error;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::{get, options, State};
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct TransitionRecord {
+    pub event: String,
+    pub region_id: Option<String>,
+    pub region_description: Option<String>,
+    pub region_created_at: i64,
+    pub report_trigger: String,
+    pub horizontal_accuracy: Option<i32>,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub measurement_time: i64,
+}
+
+impl From<Transition> for TransitionRecord {
+    fn from(transition: Transition) -> Self {
+        TransitionRecord {
+            event: transition.event,
+            region_id: transition.region_id,
+            region_description: transition.region_description,
+            region_created_at: transition.region_created_at.and_utc().timestamp(),
+            report_trigger: transition.report_trigger,
+            horizontal_accuracy: transition.horizontal_accuracy,
+            latitude: transition.latitude,
+            longitude: transition.longitude,
+            measurement_time: transition.measurement_time.and_utc().timestamp(),
+        }
+    }
+}
+
+#[options("/devices/<_device_id>/transitions")]
+pub fn get_transitions_options(_device_id: i32) -> Status {
+    Status::Ok
+}
+
+#[get("/devices/<device_id>/transitions?<region>")]
+pub fn get_transitions(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    device_id: i32,
+    region: Option<String>,
+) -> Result<Json<Vec<TransitionRecord>>, Status> {
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+    authenticated_user.ensure_device_access(device_id, &mut db_connection)?;
+
+    let transition_records = db_connection
+        .build_transaction()
+        .read_only()
+        .run::<_, diesel::result::Error, _>(|connection| {
+            let mut query = transitions
+                .filter(reporting_device.eq(device_id))
+                .order_by(measurement_time.desc())
+                .into_boxed();
+            if let Some(region) = region {
+                query = query.filter(region_id.eq(region));
+            }
+            query.load::<Transition>(connection)
+        })
+        .map_err(|error| {
+            error!(
+                "Could not query the transitions of the device {}. The error was: {}",
+                device_id, error
+            );
+            Status::InternalServerError
+        })?;
+
+    Ok(Json(
+        transition_records
+            .into_iter()
+            .map(TransitionRecord::from)
+            .collect(),
+    ))
+}
@@ -0,0 +1,468 @@
+use crate::fairings::ThereIWasDatabaseConnection;
+use crate::guards::{
+    AuthenticatedUser, CreateUsersPermission, DeleteUsersPermission, EditUsersPermission,
+    ViewUsersPermission,
+};
+use crate::models::{NewUser, NewUserToRole, User};
+use crate::schema::users::dsl::users;
+use crate::schema::users::{id as user_id_column, password_hash, username};
+use crate::{
+    hash_password, log_audit_message, AuditLogAction, AuditLogResult, MINIMUM_PASSWORD_LENGTH,
+};
+use diesel::result::DatabaseErrorKind;
+use diesel::{Connection, ExpressionMethods, JoinOnDsl, PgConnection, QueryDsl, RunQueryDsl};
+use log::{error, info, warn};
+use rocket::http::Status;
+use rocket::response::status::Created;
+use rocket::serde::json::Json;
+use rocket::{delete, get, options, post, put, State};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+/// The maximal number of characters of a username (see the `users` table).
+const MAXIMUM_USERNAME_LENGTH: usize = 64;
+
+#[derive(Serialize)]
+pub struct UserRecord {
+    pub id: i32,
+    pub username: String,
+    pub roles: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct NewUserRequest {
+    /// The name the new user will use for logging in.
+    pub username: String,
+    /// The initial password of the new user.
+    pub password: String,
+    /// The names of the roles which should be assigned to the new user.
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UserRolesRequest {
+    /// The names of the roles the user should have after the request.
+    pub roles: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordChangeRequest {
+    /// The password the user currently uses for logging in.
+    pub current_password: String,
+    /// The password which should be used from now on.
+    pub new_password: String,
+}
+
+/// The errors which can occur while changing the users or the role assignments.
+enum UserManagementError {
+    /// At least one of the requested roles does not exist.
+    UnknownRole,
+    /// The user which should be changed does not exist.
+    UnknownUser,
+    /// There is already a user with the requested name.
+    UsernameAlreadyTaken,
+    /// The database reported an error.
+    DatabaseError(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for UserManagementError {
+    fn from(error: diesel::result::Error) -> Self {
+        match error {
+            diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                UserManagementError::UsernameAlreadyTaken
+            }
+            _ => UserManagementError::DatabaseError(error),
+        }
+    }
+}
+
+/// Check if the supplied username can be used for a new user.
+fn is_valid_username(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().count() <= MAXIMUM_USERNAME_LENGTH
+        && !name.chars().any(char::is_whitespace)
+}
+
+/// Check if the supplied password is acceptable for a user account.
+fn is_valid_password(password: &str) -> bool {
+    password.chars().count() >= MINIMUM_PASSWORD_LENGTH
+}
+
+/// Replace all roles of the supplied user by the roles with the supplied names.
+fn assign_roles(
+    user: i32,
+    role_names: &[String],
+    connection: &mut PgConnection,
+) -> Result<Vec<String>, UserManagementError> {
+    use crate::schema::roles::dsl::roles;
+    use crate::schema::roles::{id as role_id_column, name as role_name};
+    use crate::schema::users_to_roles::dsl::users_to_roles;
+    use crate::schema::users_to_roles::user_id;
+
+    let requested_roles = role_names.iter().collect::<HashSet<_>>();
+    let found_roles = roles
+        .filter(role_name.eq_any(&requested_roles))
+        .order_by(role_name.asc())
+        .select((role_id_column, role_name))
+        .load::<(i32, String)>(connection)?;
+    if found_roles.len() != requested_roles.len() {
+        return Err(UserManagementError::UnknownRole);
+    }
+
+    diesel::delete(users_to_roles.filter(user_id.eq(user))).execute(connection)?;
+    if !found_roles.is_empty() {
+        diesel::insert_into(users_to_roles)
+            .values(
+                found_roles
+                    .iter()
+                    .map(|(role_id, _)| NewUserToRole {
+                        user_id: user,
+                        role_id: *role_id,
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .execute(connection)?;
+    }
+
+    Ok(found_roles.into_iter().map(|(_, name)| name).collect())
+}
+
+#[options("/users")]
+pub fn get_users_options() -> Status {
+    Status::Ok
+}
+
+#[options("/users/<_user>")]
+pub fn get_user_options(_user: i32) -> Status {
+    Status::Ok
+}
+
+#[options("/users/<_user>/roles")]
+pub fn get_user_roles_options(_user: i32) -> Status {
+    Status::Ok
+}
+
+#[options("/users/me/password")]
+pub fn get_password_options() -> Status {
+    Status::Ok
+}
+
+#[get("/users")]
+pub fn get_users(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    _authenticated_user: ViewUsersPermission,
+) -> Result<Json<Vec<UserRecord>>, Status> {
+    use crate::schema::roles::dsl::roles;
+    use crate::schema::users_to_roles::dsl::users_to_roles;
+    use crate::schema::{roles as role, users_to_roles as user_role};
+
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+
+    let (registered_users, assigned_roles) = db_connection
+        .build_transaction()
+        .read_only()
+        .run::<_, diesel::result::Error, _>(|connection| {
+            let registered_users = users.order_by(username.asc()).load::<User>(connection)?;
+            let assigned_roles = users_to_roles
+                .inner_join(roles.on(role::id.eq(user_role::role_id)))
+                .order_by(role::name.asc())
+                .select((user_role::user_id, role::name))
+                .load::<(i32, String)>(connection)?;
+            Ok((registered_users, assigned_roles))
+        })
+        .map_err(|error| {
+            error!(
+                "Could not query the registered users. The error was: {}",
+                error
+            );
+            Status::InternalServerError
+        })?;
+
+    let mut roles_of_users = HashMap::<i32, Vec<String>>::new();
+    for (user_id, role_name) in assigned_roles {
+        roles_of_users.entry(user_id).or_default().push(role_name);
+    }
+
+    Ok(Json(
+        registered_users
+            .into_iter()
+            .map(|user| UserRecord {
+                roles: roles_of_users.remove(&user.id).unwrap_or_default(),
+                id: user.id,
+                username: user.username,
+            })
+            .collect(),
+    ))
+}
+
+#[post("/users", data = "<new_user>")]
+pub fn add_user(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: CreateUsersPermission,
+    new_user: Json<NewUserRequest>,
+    client_ip: Option<IpAddr>,
+) -> Result<Created<Json<UserRecord>>, Status> {
+    let remote_endppoint = client_ip.unwrap_or(IpAddr::from([0, 0, 0, 0])).to_string();
+
+    if !is_valid_username(&new_user.username) || !is_valid_password(&new_user.password) {
+        return Err(Status::UnprocessableEntity);
+    }
+    let hashed_password = hash_password(&new_user.password).ok_or(Status::InternalServerError)?;
+
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+
+    let creation_result = db_connection.transaction::<_, UserManagementError, _>(|connection| {
+        let created_user = diesel::insert_into(users)
+            .values(&NewUser {
+                username: new_user.username.clone(),
+                password_hash: hashed_password,
+            })
+            .get_result::<User>(connection)?;
+        let assigned_roles = assign_roles(created_user.id, &new_user.roles, connection)?;
+        Ok(UserRecord {
+            id: created_user.id,
+            username: created_user.username,
+            roles: assigned_roles,
+        })
+    });
+
+    match creation_result {
+        Ok(created_user) => {
+            info!(
+                "The user '{}' was created by '{}'",
+                created_user.username, authenticated_user.0.username
+            );
+            log_audit_message(
+                &mut db_connection,
+                AuditLogAction::UserCreation,
+                AuditLogResult::Successful,
+                &remote_endppoint,
+            );
+            Ok(Created::new(format!("/v1/users/{}", created_user.id)).body(Json(created_user)))
+        }
+        Err(UserManagementError::UsernameAlreadyTaken) => Err(Status::Conflict),
+        Err(UserManagementError::UnknownRole) | Err(UserManagementError::UnknownUser) => {
+            Err(Status::UnprocessableEntity)
+        }
+        Err(UserManagementError::DatabaseError(error)) => {
+            error!(
+                "Could not create the user '{}'. The error was: {}",
+                new_user.username, error
+            );
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[put("/users/<user>/roles", data = "<requested_roles>")]
+pub fn set_user_roles(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: EditUsersPermission,
+    user: i32,
+    requested_roles: Json<UserRolesRequest>,
+    client_ip: Option<IpAddr>,
+) -> Result<Json<UserRecord>, Status> {
+    let remote_endppoint = client_ip.unwrap_or(IpAddr::from([0, 0, 0, 0])).to_string();
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+
+    let assignment_result = db_connection.transaction::<_, UserManagementError, _>(|connection| {
+        let Some(found_user) = users
+            .find(user)
+            .load::<User>(connection)?
+            .into_iter()
+            .next()
+        else {
+            return Err(UserManagementError::UnknownUser);
+        };
+        let assigned_roles = assign_roles(found_user.id, &requested_roles.roles, connection)?;
+        Ok(UserRecord {
+            id: found_user.id,
+            username: found_user.username,
+            roles: assigned_roles,
+        })
+    });
+
+    match assignment_result {
+        Ok(changed_user) => {
+            info!(
+                "The roles of the user '{}' were set to {:?} by '{}'",
+                changed_user.username, changed_user.roles, authenticated_user.0.username
+            );
+            log_audit_message(
+                &mut db_connection,
+                AuditLogAction::RoleAssignment,
+                AuditLogResult::Successful,
+                &remote_endppoint,
+            );
+            Ok(Json(changed_user))
+        }
+        Err(UserManagementError::UnknownUser) => Err(Status::NotFound),
+        Err(UserManagementError::UnknownRole) | Err(UserManagementError::UsernameAlreadyTaken) => {
+            Err(Status::UnprocessableEntity)
+        }
+        Err(UserManagementError::DatabaseError(error)) => {
+            error!(
+                "Could not assign the roles of the user {}. The error was: {}",
+                user, error
+            );
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[put("/users/me/password", data = "<password_change>")]
+pub fn change_password(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: AuthenticatedUser,
+    password_change: Json<PasswordChangeRequest>,
+    client_ip: Option<IpAddr>,
+) -> Result<Status, Status> {
+    let remote_endppoint = client_ip.unwrap_or(IpAddr::from([0, 0, 0, 0])).to_string();
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+
+    let stored_password_hash = users
+        .find(authenticated_user.id)
+        .select(password_hash)
+        .first::<String>(&mut db_connection)
+        .map_err(|error| {
+            error!(
+                "Could not get the password of the user '{}'. The error was: {}",
+                authenticated_user.username, error
+            );
+            Status::InternalServerError
+        })?;
+
+    // the current password is required to prevent a stolen access token from taking over the account
+    let is_password_correct = bcrypt::verify(
+        &password_change.current_password,
+        stored_password_hash.as_str(),
+    )
+    .map_err(|error| {
+        error!("Could not verify the supplied password with the one stored in the database. The error was: {}", error);
+        Status::InternalServerError
+    })?;
+    if !is_password_correct {
+        warn!(
+            "The user '{}' supplied a wrong password while trying to change it",
+            authenticated_user.username
+        );
+        log_audit_message(
+            &mut db_connection,
+            AuditLogAction::PasswordChange,
+            AuditLogResult::Failed,
+            &remote_endppoint,
+        );
+        return Err(Status::Forbidden);
+    }
+
+    if !is_valid_password(&password_change.new_password) {
+        return Err(Status::UnprocessableEntity);
+    }
+    let hashed_password =
+        hash_password(&password_change.new_password).ok_or(Status::InternalServerError)?;
+
+    db_connection
+        .transaction::<_, diesel::result::Error, _>(|connection| {
+            use crate::schema::refresh_tokens::dsl::refresh_tokens;
+            use crate::schema::refresh_tokens::user_id;
+
+            diesel::update(users.find(authenticated_user.id))
+                .set(password_hash.eq(hashed_password))
+                .execute(connection)?;
+            // a refresh token which was stolen before should not outlive the password change, so
+            // all sessions of the user have to log in again
+            diesel::delete(refresh_tokens.filter(user_id.eq(authenticated_user.id)))
+                .execute(connection)
+        })
+        .map_err(|error| {
+            error!(
+                "Could not store the new password of the user '{}'. The error was: {}",
+                authenticated_user.username, error
+            );
+            Status::InternalServerError
+        })?;
+
+    log_audit_message(
+        &mut db_connection,
+        AuditLogAction::PasswordChange,
+        AuditLogResult::Successful,
+        &remote_endppoint,
+    );
+
+    Ok(Status::NoContent)
+}
+
+#[delete("/users/<user>")]
+pub fn delete_user(
+    db_connection_pool: &State<ThereIWasDatabaseConnection>,
+    authenticated_user: DeleteUsersPermission,
+    user: i32,
+    client_ip: Option<IpAddr>,
+) -> Result<Status, Status> {
+    use crate::schema::users_to_roles::dsl::users_to_roles;
+    use crate::schema::users_to_roles::user_id;
+
+    let remote_endppoint = client_ip.unwrap_or(IpAddr::from([0, 0, 0, 0])).to_string();
+
+    // a user should not be able to lock themselves out of the instance
+    if user == authenticated_user.0.id {
+        return Err(Status::UnprocessableEntity);
+    }
+
+    let mut db_connection = db_connection_pool
+        .get()
+        .map_err(|_| Status::ServiceUnavailable)?;
+
+    match db_connection.transaction::<_, diesel::result::Error, _>(|connection| {
+        diesel::delete(users_to_roles.filter(user_id.eq(user))).execute(connection)?;
+        diesel::delete(users.filter(user_id_column.eq(user))).execute(connection)
+    }) {
+        Ok(0) => Err(Status::NotFound),
+        Ok(_) => {
+            info!(
+                "The user {} was deleted by '{}'",
+                user, authenticated_user.0.username
+            );
+            log_audit_message(
+                &mut db_connection,
+                AuditLogAction::UserDeletion,
+                AuditLogResult::Successful,
+                &remote_endppoint,
+            );
+            Ok(Status::NoContent)
+        }
+        Err(error) => {
+            error!(
+                "Could not delete the user {}. The error was: {}",
+                user, error
+            );
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usernames_and_passwords_are_validated() {
+        assert!(is_valid_username("demo"));
+        assert!(!is_valid_username(""));
+        assert!(!is_valid_username("john doe"));
+        assert!(!is_valid_username(&"a".repeat(MAXIMUM_USERNAME_LENGTH + 1)));
+        assert!(is_valid_password("correcthorse"));
+        assert!(!is_valid_password("short"));
+    }
+}
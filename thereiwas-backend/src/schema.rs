@@ -10,13 +10,97 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    beacon_sightings (id) {
+        id -> Int4,
+        description -> Nullable<Varchar>,
+        beacon_uuid -> Varchar,
+        beacon_major -> Int4,
+        beacon_minor -> Int4,
+        proximity -> Int4,
+        rssi -> Int4,
+        horizontal_accuracy -> Nullable<Int4>,
+        measurement_time -> Timestamp,
+        reporting_device -> Int4,
+    }
+}
+
 diesel::table! {
     client_tokens (id) {
         id -> Int4,
         client -> Varchar,
-        secret -> Varchar,
+        secret_hash -> Varchar,
         description -> Nullable<Varchar>,
         health_callback_url -> Nullable<Varchar>,
+        encryption_key -> Nullable<Varchar>,
+        reported_user -> Nullable<Varchar>,
+        reported_device -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        user_id -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    device_cards (id) {
+        id -> Int4,
+        reporting_device -> Int4,
+        tracker_id -> Nullable<Varchar>,
+        name -> Varchar,
+        face -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    device_commands (id) {
+        id -> Int4,
+        reporting_device -> Int4,
+        payload -> Jsonb,
+        created_at -> Timestamp,
+        delivered_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    device_status (id) {
+        id -> Int4,
+        reporting_device -> Int4,
+        request_id -> Nullable<Varchar>,
+        received_at -> Timestamp,
+        altimeter_authorization_status -> Nullable<Varchar>,
+        altimeter_is_relative_altitude_available -> Nullable<Bool>,
+        background_refresh_status -> Nullable<Varchar>,
+        device_identifier_for_vendor -> Nullable<Varchar>,
+        device_model -> Nullable<Varchar>,
+        device_system_name -> Nullable<Varchar>,
+        device_system_version -> Nullable<Varchar>,
+        device_user_interface_idiom -> Nullable<Varchar>,
+        locale -> Nullable<Varchar>,
+        locale_uses_metric_system -> Nullable<Bool>,
+        location_manager_authorization_status -> Nullable<Varchar>,
+        app_version -> Nullable<Varchar>,
+        app_hibernation -> Nullable<Int4>,
+        battery_optimizations -> Nullable<Int4>,
+        location_permission -> Nullable<Int4>,
+        power_save -> Nullable<Int4>,
+        wifi_state -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    last_wills (id) {
+        id -> Int4,
+        connected_at -> Timestamp,
+        received_at -> Timestamp,
+        reporting_device -> Int4,
+    }
+}
+
+diesel::table! {
+    location_shares (id) {
+        id -> Int4,
+        sharing_device -> Int4,
+        viewing_device -> Int4,
     }
 }
 
@@ -33,6 +117,16 @@ diesel::table! {
         barometric_pressure -> Nullable<Float8>,
         created_at -> Nullable<Timestamp>,
         reporting_device -> Int4,
+        battery_level -> Nullable<Int2>,
+        battery_status -> Nullable<Int2>,
+        monitoring_mode -> Nullable<Int2>,
+        velocity -> Nullable<Int4>,
+        course_over_ground -> Nullable<Int4>,
+        connection_type -> Nullable<Varchar>,
+        topic -> Nullable<Varchar>,
+        in_regions -> Nullable<Array<Nullable<Text>>>,
+        in_region_ids -> Nullable<Array<Nullable<Text>>>,
+        grid_cell -> Int4,
     }
 }
 
@@ -52,6 +146,41 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    refresh_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token_hash -> Varchar,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    regions (id) {
+        id -> Int4,
+        reporting_device -> Int4,
+        region_id -> Nullable<Varchar>,
+        description -> Varchar,
+        latitude -> Float8,
+        longitude -> Float8,
+        radius -> Int4,
+        beacon_uuid -> Nullable<Varchar>,
+        beacon_major -> Nullable<Int4>,
+        beacon_minor -> Nullable<Int4>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    revoked_access_tokens (id) {
+        id -> Int4,
+        token_id -> Varchar,
+        expires_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     roles (id) {
         id -> Int4,
@@ -68,6 +197,36 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    step_counts (id) {
+        id -> Int4,
+        steps -> Int4,
+        distance -> Nullable<Int4>,
+        floors_up -> Nullable<Int4>,
+        floors_down -> Nullable<Int4>,
+        period_start -> Timestamp,
+        period_end -> Timestamp,
+        measurement_time -> Timestamp,
+        reporting_device -> Int4,
+    }
+}
+
+diesel::table! {
+    transitions (id) {
+        id -> Int4,
+        event -> Varchar,
+        region_id -> Nullable<Varchar>,
+        region_description -> Nullable<Varchar>,
+        region_created_at -> Timestamp,
+        report_trigger -> Varchar,
+        horizontal_accuracy -> Nullable<Int4>,
+        latitude -> Float8,
+        longitude -> Float8,
+        measurement_time -> Timestamp,
+        reporting_device -> Int4,
+    }
+}
+
 diesel::table! {
     users (id) {
         id -> Int4,
@@ -93,21 +252,42 @@ diesel::table! {
     }
 }
 
+diesel::joinable!(beacon_sightings -> client_tokens (reporting_device));
+diesel::joinable!(client_tokens -> users (user_id));
+diesel::joinable!(device_cards -> client_tokens (reporting_device));
+diesel::joinable!(device_commands -> client_tokens (reporting_device));
+diesel::joinable!(device_status -> client_tokens (reporting_device));
+diesel::joinable!(last_wills -> client_tokens (reporting_device));
 diesel::joinable!(locations_to_wifi_access_points -> locations (location_id));
 diesel::joinable!(locations_to_wifi_access_points -> wifi_access_points (wifi_access_point_id));
+diesel::joinable!(refresh_tokens -> users (user_id));
+diesel::joinable!(regions -> client_tokens (reporting_device));
 diesel::joinable!(roles_to_permissions -> permissions (permission_id));
 diesel::joinable!(roles_to_permissions -> roles (role_id));
+diesel::joinable!(step_counts -> client_tokens (reporting_device));
+diesel::joinable!(transitions -> client_tokens (reporting_device));
 diesel::joinable!(users_to_roles -> roles (role_id));
 diesel::joinable!(users_to_roles -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     audit_log,
+    beacon_sightings,
     client_tokens,
+    device_cards,
+    device_commands,
+    device_status,
+    last_wills,
+    location_shares,
     locations,
     locations_to_wifi_access_points,
     permissions,
+    refresh_tokens,
+    regions,
+    revoked_access_tokens,
     roles,
     roles_to_permissions,
+    step_counts,
+    transitions,
     users,
     users_to_roles,
     wifi_access_points,
@@ -0,0 +1,30 @@
+use crate::models::Location;
+
+/// Get a location for tests which only differs from other test locations in the supplied values.
+pub fn get_test_location(id: i32, reporting_device: i32, timestamp: i64) -> Location {
+    Location {
+        id,
+        horizontal_accuracy: Some(12),
+        altitude: Some(38),
+        latitude: 51.2353,
+        longitude: 6.7829,
+        report_trigger: "p".to_string(),
+        measurement_time: chrono::DateTime::from_timestamp(timestamp, 0)
+            .unwrap()
+            .naive_utc(),
+        vertical_accuracy: None,
+        barometric_pressure: Some(101.3),
+        created_at: None,
+        reporting_device,
+        battery_level: None,
+        battery_status: None,
+        monitoring_mode: None,
+        velocity: None,
+        course_over_ground: None,
+        connection_type: None,
+        topic: None,
+        in_regions: None,
+        in_region_ids: None,
+        grid_cell: 5085067,
+    }
+}
@@ -10,4 +10,5 @@ export const API_BACKEND_URL = `${determineApiUrl()}/v1`;
 
 export interface AccessToken {
   accessToken: string;
+  refreshToken: string;
 }
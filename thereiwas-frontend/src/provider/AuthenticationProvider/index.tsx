@@ -1,8 +1,12 @@
-import { useState } from "react";
+import { useEffect, useState } from "react";
 import * as React from "react";
+import { decodeJwt } from "jose";
 import { AuthenticationContext } from "../../hooks/useAuthentication";
 import { AccessToken, API_BACKEND_URL } from "../../api";
 
+// the number of seconds before the access token expires in which it will be refreshed
+const TOKEN_REFRESH_MARGIN = 60;
+
 export const AuthenticationProvider = ({
   children,
 }: {
@@ -14,9 +18,53 @@ export const AuthenticationProvider = ({
     if (tokenSessionStorage) {
       return JSON.parse(tokenSessionStorage);
     }
-    return { accessToken: "" };
+    return { accessToken: "", refreshToken: "" };
   });
 
+  const storeToken = (receivedToken: AccessToken) => {
+    setToken(receivedToken);
+    window.sessionStorage.setItem(
+      "thereiwas:token",
+      JSON.stringify(receivedToken),
+    );
+  };
+
+  const clearToken = () => {
+    setToken({ accessToken: "", refreshToken: "" });
+    window.sessionStorage.removeItem("thereiwas:token");
+  };
+
+  useEffect(() => {
+    if (!token.accessToken || !token.refreshToken) {
+      return;
+    }
+
+    // get a new access token shortly before the current one expires
+    const expiresAt = decodeJwt(token.accessToken).exp || 0;
+    const refreshIn = Math.max(
+      expiresAt - TOKEN_REFRESH_MARGIN - Date.now() / 1000,
+      0,
+    );
+    const timeout = setTimeout(() => {
+      fetch(`${API_BACKEND_URL}/auth/refresh`, {
+        method: "POST",
+        body: JSON.stringify({ refreshToken: token.refreshToken }),
+        headers: {
+          "Content-type": "application/json; charset=UTF-8",
+        },
+      })
+        .then((response) => {
+          if (response.status !== 200) {
+            return Promise.reject();
+          }
+          return response.json();
+        })
+        .then((receivedToken: AccessToken) => storeToken(receivedToken))
+        .catch(() => clearToken());
+    }, refreshIn * 1000);
+    return () => clearTimeout(timeout);
+  }, [token]);
+
   const signin = (
     username: string,
     password: string,
@@ -45,11 +93,7 @@ export const AuthenticationProvider = ({
       })
       .then((response) => response.json())
       .then((receivedToken: AccessToken) => {
-        setToken(receivedToken);
-        window.sessionStorage.setItem(
-          "thereiwas:token",
-          JSON.stringify(receivedToken),
-        );
+        storeToken(receivedToken);
         successCallback();
       })
       .catch(() => {
@@ -58,8 +102,18 @@ export const AuthenticationProvider = ({
   };
 
   const signout = (callback: VoidFunction) => {
-    setToken({ accessToken: "" });
-    window.sessionStorage.removeItem("thereiwas:token");
+    // revoke the tokens on the server side, so they can not be used anymore
+    if (token.accessToken) {
+      fetch(`${API_BACKEND_URL}/auth/logout`, {
+        method: "POST",
+        body: JSON.stringify({ refreshToken: token.refreshToken }),
+        headers: {
+          "Content-type": "application/json; charset=UTF-8",
+          Authorization: `Bearer ${token.accessToken}`,
+        },
+      }).catch(() => {});
+    }
+    clearToken();
     callback();
   };
 
@@ -2,6 +2,7 @@ import { useEffect, useState } from "react";
 import { MapContainer, TileLayer, Marker, Popup, useMap } from "react-leaflet";
 import { Box, Paper, Typography } from "@mui/material";
 import { LatLngExpression } from "leaflet";
+import { useAuthentication } from "../../hooks/useAuthentication";
 
 interface Position {
   longitude: number;
@@ -12,6 +13,11 @@ interface Position {
   measurement_time: number;
 }
 
+interface PositionsResponse {
+  positions: Position[];
+  next_cursor: string | null;
+}
+
 const MIN_HORIZONTAL_ACCURACY = 15; // minimum accuracy threshold in meters
 
 const BoundsUpdater = ({ positions }: { positions: Position[] }) => {
@@ -32,6 +38,7 @@ const BoundsUpdater = ({ positions }: { positions: Position[] }) => {
 };
 
 export const DashboardView = () => {
+  const auth = useAuthentication();
   const [positions, setPositions] = useState<Position[]>([]);
   const [mapCenter, setMapCenter] = useState<LatLngExpression>([
     51.235344, 6.782973,
@@ -40,14 +47,18 @@ export const DashboardView = () => {
   useEffect(() => {
     const fetchPositions = async () => {
       try {
-        const response = await fetch("http://localhost:3000/v1/positions");
+        const response = await fetch("http://localhost:3000/v1/positions", {
+          headers: {
+            Authorization: `Bearer ${auth.token.accessToken}`,
+          },
+        });
         if (!response.ok) {
           throw new Error("Failed to fetch positions");
         }
-        const data: Position[] = await response.json();
+        const data: PositionsResponse = await response.json();
 
         // Filter positions by horizontal accuracy
-        const accuratePositions = data.filter(
+        const accuratePositions = data.positions.filter(
           (position) => position.horizontal_accuracy <= MIN_HORIZONTAL_ACCURACY,
         );
 
@@ -68,7 +79,7 @@ export const DashboardView = () => {
     fetchPositions();
     const interval = setInterval(fetchPositions, 30000);
     return () => clearInterval(interval);
-  }, []);
+  }, [auth.token.accessToken]);
 
   return (
     <Box